@@ -1,12 +1,6 @@
 use std::io::Read;
 use std::fs::File;
-use ddd::{
-    ConfigProfile,
-    parsing::{detect_stl_type, parse_mesh_file, MeshFileUnits},
-    slice::Slicer,
-    mesh::Scene,
-    gcode::slices_to_gcode,
-};
+use ddd::{ConfigProfile, parsing::MeshFileUnits};
 
 fn main() {
     let filename = std::env::args().skip(1).next();
@@ -19,19 +13,14 @@ fn main() {
         return;
     }
 
-    let mesh = parse_mesh_file(&filebytes, detect_stl_type(&filebytes), MeshFileUnits::Millimeters).unwrap();
-    let mut scene = Scene::new();
-    scene.add_mesh(mesh);
-
-    let config = ConfigProfile {
-        layer_height: 200_000,
-        hotend_temperature: 100,
-        travel_speed: 5,
-    };
-    let slicer = Slicer::new(&config);
-
-    let slices = slicer.slice(scene).unwrap();
-    let gcode = slices_to_gcode(&config, &slices);
+    let config = ConfigProfile::builder()
+        .layer_height(200_000)
+        .hotend_temperature(200)
+        .bed_temperature(60)
+        .end_gcode(Some("M104 S0\nM140 S0\nM107\nG28 X".to_string()))
+        .build()
+        .unwrap();
+    let gcode = ddd::slice_file(&filebytes, MeshFileUnits::Millimeters, &config).unwrap();
 
     println!("{gcode}");
 }
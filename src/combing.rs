@@ -0,0 +1,105 @@
+use crate::geometry::{segment_crossing, Polygon, Vector2D};
+use crate::slice::SliceIsland;
+
+/// More waypoints than this make finding a path too slow, so travel moves just cross the boundary instead
+const MAX_WAYPOINTS: usize = 256;
+
+/// The area of an island that travel moves can stay inside of, so that they don't cross open space
+/// (where the nozzle would leave strings) and don't need a retraction.
+pub(crate) struct CombingBoundary {
+    outline: Polygon,
+    holes: Vec<Polygon>,
+    /// Points that paths can bend around: the vertices of the outermost perimeter that stick into the
+    /// island (or, around holes, into the hole). A shortest path only ever bends at such vertices.
+    waypoints: Vec<Vector2D>,
+}
+
+impl CombingBoundary {
+    pub(crate) fn new(island: &SliceIsland) -> Self {
+        let mut waypoints = Vec::new();
+        for perimeter in island.perimeters().first().into_iter().flatten() {
+            // the closing vertex is a duplicate of the first
+            let vertices = &perimeter.vertices()[..perimeter.vertices().len() - 1];
+            for (index, vertex) in vertices.iter().enumerate() {
+                let prev = &vertices[(index + vertices.len() - 1) % vertices.len()];
+                let next = &vertices[(index + 1) % vertices.len()];
+                // outlines go counterclockwise and holes clockwise, so the inside of the island is always on the
+                // left, and a right turn bends around something
+                if vertex.sub(prev).cross(&next.sub(vertex)) < 0 {
+                    waypoints.push(vertex.clone());
+                }
+            }
+        }
+        Self {
+            outline: island.outline().clone(),
+            holes: island.holes().to_vec(),
+            waypoints,
+        }
+    }
+
+    /// The points to travel through to get from `from` to `to` without leaving the island, ending with `to`.
+    /// Returns `None` if either point is outside of the island or there is no such path.
+    pub(crate) fn route(&self, from: &Vector2D, to: &Vector2D) -> Option<Vec<Vector2D>> {
+        if !self.contains(from) || !self.contains(to) {
+            return None;
+        }
+        if self.is_visible(from, to) {
+            return Some(vec![to.clone()]);
+        }
+        if self.waypoints.len() > MAX_WAYPOINTS {
+            return None;
+        }
+
+        // Dijkstra's algorithm over the waypoints, checking which ones can see each other as it goes.
+        // Node 0 is `from` and the last node is `to`.
+        let nodes: Vec<&Vector2D> = std::iter::once(from).chain(&self.waypoints).chain(std::iter::once(to)).collect();
+        let mut distances = vec![f64::INFINITY; nodes.len()];
+        let mut previous = vec![None; nodes.len()];
+        let mut visited = vec![false; nodes.len()];
+        distances[0] = 0.0;
+        loop {
+            let (current, _) = distances.iter()
+                .enumerate()
+                .filter(|(index, distance)| !visited[*index] && distance.is_finite())
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+            if current == nodes.len() - 1 {
+                break;
+            }
+            visited[current] = true;
+            for next in 0..nodes.len() {
+                if visited[next] {
+                    continue;
+                }
+                let distance = distances[current] + nodes[current].distance_to(nodes[next]);
+                if distance < distances[next] && self.is_visible(nodes[current], nodes[next]) {
+                    distances[next] = distance;
+                    previous[next] = Some(current);
+                }
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut current = nodes.len() - 1;
+        while current != 0 {
+            path.push(nodes[current].clone());
+            current = previous[current].unwrap();
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Whether `point` is inside the outline and not inside any of the holes
+    pub(crate) fn contains(&self, point: &Vector2D) -> bool {
+        self.outline.contains(point) && !self.holes.iter().any(|hole| hole.contains(point))
+    }
+
+    /// Whether the straight line from `a` to `b` stays inside the island
+    fn is_visible(&self, a: &Vector2D, b: &Vector2D) -> bool {
+        let crosses_boundary = std::iter::once(&self.outline).chain(&self.holes)
+            .flat_map(|polygon| polygon.vertices().windows(2))
+            .any(|edge| segment_crossing(a, b, &edge[0], &edge[1]).is_some());
+        // a line that passes exactly through a vertex of the boundary doesn't cross any edges, but can still
+        // leave the island
+        !crosses_boundary && self.contains(&Vector2D::new(a.x / 2 + b.x / 2, a.y / 2 + b.y / 2))
+    }
+}
@@ -0,0 +1,588 @@
+use crate::gcode::{FirmwareFlavor, Precision, SeamPosition};
+use crate::geometry::Vector3D;
+use crate::Error;
+
+/// Highest hotend temperature accepted by [ConfigProfile::validate]
+const MAX_HOTEND_TEMPERATURE: u32 = 450;
+/// Highest bed temperature accepted by [ConfigProfile::validate]
+const MAX_BED_TEMPERATURE: u32 = 150;
+/// Extrusion width used when [ConfigProfile::extrusion_width] isn't set, as a multiple of the nozzle diameter
+const EXTRUSION_WIDTH_PER_NOZZLE_DIAMETER: f64 = 1.2;
+
+/// Global configuration.
+///
+/// Create one with [ConfigProfile::builder], which fills in defaults for anything that isn't set.
+///
+/// With the `serde` feature, profiles can be (de)serialized, e.g. loaded from TOML with
+/// [ConfigProfile::from_toml_str]. Lengths that are stored in nanometers (`layer_height`,
+/// `extrusion_width`, `retraction.length`, etc) are written in millimeters in serialized profiles, and
+/// rounded to the nearest nanometer when loaded. Everything else uses the same units as the fields.
+/// Settings missing from a serialized profile get their default values.
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+pub struct ConfigProfile {
+    /// Thickness of each printed slice (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub layer_height: u64,
+    /// Thickness of the first slice (in nanometers), or `None` to use `layer_height`. A thicker first
+    /// layer sticks to the bed better.
+    #[cfg_attr(feature = "serde", serde(with = "mm_option"))]
+    pub first_layer_height: Option<u64>,
+    /// Vary the thickness of the slices above the first one with the slope of the model's surface, or
+    /// `None` to always use `layer_height`
+    pub adaptive_layer_height: Option<AdaptiveLayerHeight>,
+    pub hotend_temperature: u32,
+    /// Heated bed temperature, or 0 for printers without a heated bed
+    pub bed_temperature: u32,
+    /// Hotend temperatures to switch to partway through the print, as `(height, temperature)` pairs (heights
+    /// in nanometers), which [ConfigProfileBuilder::build] and [ConfigProfile::from_toml_str] sort by height.
+    /// Each temperature is set at the start of the first layer whose top is at least that high, without
+    /// waiting for it to be reached. Useful for printing temperature towers.
+    #[cfg_attr(feature = "serde", serde(with = "mm_schedule"))]
+    pub temperature_schedule: Vec<(i64, u32)>,
+    /// Speed to move when not extruding (in mm/min)
+    pub travel_speed: u32,
+    /// Speed of extrusion moves along the outermost perimeter, which is the visible surface of the model
+    /// (in mm/min)
+    pub outer_perimeter_speed: u32,
+    /// Speed of extrusion moves along the other perimeters (in mm/min)
+    pub inner_perimeter_speed: u32,
+    /// Speed of extrusion moves for sparse and solid infill (in mm/min)
+    pub infill_speed: u32,
+    /// Speed of all extrusion moves on the first layer of the model (above the raft, if there is one),
+    /// and of the skirt and brim (in mm/min)
+    pub first_layer_speed: u32,
+    /// Speed of extrusion moves for bridges, which print over open space (in mm/min)
+    pub bridge_speed: u32,
+    /// Number of concentric loops printed along the walls of each island
+    pub perimeters: u32,
+    /// Print the outermost perimeter of each island before the inner ones, which makes outer dimensions more
+    /// accurate. Otherwise it is printed last, which hides the seams where loops start and end better.
+    pub external_perimeters_first: bool,
+    /// Where to start each perimeter loop
+    pub seam_position: SeamPosition,
+    /// Number of loops printed around the first layer, to prime the nozzle before printing the model
+    pub skirt_loops: u32,
+    /// Gap between the skirt and the model (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub skirt_distance: u64,
+    /// Number of loops printed around each island of the first layer, touching it, to help the model
+    /// stick to the bed
+    pub brim_loops: u32,
+    /// Number of layers of a raft to print under the model, to help it stick to the bed. The raft covers the
+    /// islands of the model's first layer grown outwards by [ConfigProfile::raft_margin] (merging islands
+    /// that grow into each other), and its top layer is filled solid. The model doesn't get a brim when
+    /// there is a raft.
+    pub raft_layers: u32,
+    /// How far the raft extends past the model (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub raft_margin: u64,
+    /// Space left between the top of the raft and the bottom of the model (in nanometers), so that the
+    /// raft can be peeled off
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub raft_air_gap: u64,
+    /// Number of layers at the top of the model that are filled solid
+    pub top_layers: u32,
+    /// Number of layers at the bottom of the model that are filled solid
+    pub bottom_layers: u32,
+    /// Diameter of the hole in the nozzle (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub nozzle_diameter: u64,
+    /// Width of a single extruded line (in nanometers), or `None` for 1.2 times `nozzle_diameter`. This is
+    /// the spacing of perimeters and infill lines, and sets how much filament is extruded. Use
+    /// [ConfigProfile::extrusion_width()] to get the width that is used.
+    #[cfg_attr(feature = "serde", serde(with = "mm_option"))]
+    pub extrusion_width: Option<u64>,
+    /// Fraction of the inside of each island that gets filled, from 0.0 (hollow) to 1.0 (solid)
+    pub infill_density: f32,
+    /// Smooth the top surfaces of the model by running the nozzle back and forth over them after they're
+    /// printed, extruding just a little to fill in gaps
+    pub ironing: bool,
+    /// How much to extrude while ironing, as a fraction of the normal amount for the distance moved
+    pub ironing_flow: f32,
+    /// Distance between neighbouring ironing lines (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub ironing_spacing: u64,
+    /// Print the model as a single spiralling wall, raising the nozzle continuously while printing each
+    /// layer's outline instead of stepping up between layers. Only the outermost perimeter is printed,
+    /// with no infill or solid layers, so every layer must be a single island without holes.
+    pub vase_mode: bool,
+    /// Leave out layers that have nothing in them (where there is a gap in the model), moving straight up past
+    /// them instead. Otherwise they're kept as empty slices, and the nozzle still steps up through each of them.
+    pub skip_empty_layers: bool,
+    /// How far (in nanometers) slice outlines may be moved to remove vertices from them (see
+    /// [Polygon::simplify]), or `None` to keep every vertex of the sliced mesh
+    ///
+    /// [Polygon::simplify]: crate::geometry::Polygon::simplify
+    #[cfg_attr(feature = "serde", serde(with = "mm_option"))]
+    pub simplify_tolerance: Option<u64>,
+    /// Shortest move to print around perimeters and other loops (in nanometers). Shorter moves are merged
+    /// with the ones after them into a single move, which extrudes as much as all of them together would.
+    /// The end of each loop is always kept, so loops still close. 0 keeps every move.
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub min_segment_length: u64,
+    /// Diameter of the filament fed into the extruder (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub filament_diameter: u64,
+    /// Density of the filament material (in g/cm^3), used to estimate the mass of a print
+    pub filament_density: f64,
+    /// Retract filament on travel moves, or `None` to never retract
+    pub retraction: Option<RetractionConfig>,
+    /// Only lift the nozzle by [RetractionConfig::z_hop] for travel moves that go straight across the outline
+    /// of an island of the layer being printed, where it could hit the printed walls. Other travel moves stay
+    /// at the height of the layer.
+    pub z_hop_only_when_crossing: bool,
+    /// How far to move the nozzle back along the path it just printed while retracting (in nanometers), to
+    /// wipe off filament that would otherwise string. 0 retracts without moving.
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub wipe_distance: u64,
+    /// How far before the end of each loop to stop extruding (in nanometers), letting the pressure left
+    /// in the nozzle finish the loop to avoid a blob at the seam. Loops that aren't longer than this aren't
+    /// coasted. 0 disables coasting.
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub coasting_distance: u64,
+    /// Route travel moves between two points of the same island around the inside of the island, instead of
+    /// in a straight line across open space. Travel moves only retract if there is no such route.
+    pub combing: bool,
+    /// Part cooling fan speed, from 0.0 (off) to 1.0 (full speed)
+    pub fan_speed: f32,
+    /// Keep the fan off while printing the first layer of the model (and the raft under it, if there is one),
+    /// for better bed adhesion
+    pub fan_first_layer_off: bool,
+    /// Part cooling fan speed while printing bridges, which need to cool quickly to not sag, from 0.0 (off)
+    /// to 1.0 (full speed)
+    pub bridge_fan_speed: f32,
+    /// Size of the space the printer can print in (in nanometers), from the origin to this corner, or `None`
+    /// to not check. Slicing fails with [Error::OutOfBounds] if the model doesn't fit (see
+    /// [Scene::fit_check](crate::mesh::Scene::fit_check)).
+    #[cfg_attr(feature = "serde", serde(with = "mm_volume"))]
+    pub build_volume: Option<Vector3D>,
+    /// Which firmware's G-code dialect to generate
+    pub firmware_flavor: FirmwareFlavor,
+    /// Accelerations to set at the start of the print, or `None` to use the printer's own settings
+    pub acceleration: Option<AccelerationConfig>,
+    /// Jerk limits to set at the start of the print, or `None` to use the printer's own settings
+    pub jerk: Option<JerkConfig>,
+    /// Linear advance (or pressure advance) factor to set at the start of the print (see
+    /// [Command::SetLinearAdvance]), or `None` to use the printer's own setting. `Some(0.0)` disables it.
+    ///
+    /// [Command::SetLinearAdvance]: crate::gcode::Command::SetLinearAdvance
+    pub linear_advance: Option<f32>,
+    /// Write extruder moves with relative `E` values (`M83`), the length of filament fed by each move,
+    /// instead of the total length fed so far
+    pub relative_extrusion: bool,
+    /// How many decimal places to write lengths in the G-code with
+    pub precision: Precision,
+    /// Annotate the G-code with comments marking each layer and the kind of path being printed (in the
+    /// format that G-code viewers understand), and a header summarizing the settings and estimates
+    pub comments: bool,
+    /// Indices of the layers to pause at the start of for the filament to be swapped (with `M600`), for
+    /// printing different layers in different colors
+    pub filament_change_layers: Vec<usize>,
+    /// Custom G-code inserted after the built-in starting sequence (which homes and heats up the printer)
+    pub start_gcode: Option<String>,
+    /// Custom G-code inserted at the very end
+    pub end_gcode: Option<String>,
+}
+
+/// Settings for pulling filament back out of the nozzle during travel moves, to prevent stringing
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetractionConfig {
+    /// Length of filament to retract (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub length: u64,
+    /// Speed to retract and unretract at (in mm/min)
+    pub speed: u32,
+    /// How far to lift the nozzle while travelling (in nanometers), or 0 to not lift it
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub z_hop: u64,
+}
+
+/// How quickly the printer speeds up and slows down (see [ConfigProfile::acceleration])
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccelerationConfig {
+    /// Acceleration of extrusion moves (in mm/s^2)
+    pub print: u32,
+    /// Acceleration of travel moves (in mm/s^2)
+    pub travel: u32,
+}
+
+/// The largest instant change in speed the printer makes on each axis, e.g. at corners, without
+/// accelerating (see [ConfigProfile::jerk])
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct JerkConfig {
+    /// In mm/s
+    pub x: u32,
+    /// In mm/s
+    pub y: u32,
+    /// In mm/s
+    pub z: u32,
+}
+
+/// Settings for varying the layer height with the slope of the model. Near-horizontal surfaces get thin
+/// layers, so that they look less like stairs, and vertical walls get thick layers to print faster.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AdaptiveLayerHeight {
+    /// Thickness of layers at the shallowest slopes (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub min_layer_height: u64,
+    /// Thickness of layers where the walls are vertical (in nanometers)
+    #[cfg_attr(feature = "serde", serde(with = "mm"))]
+    pub max_layer_height: u64,
+}
+
+impl ConfigProfile {
+    /// Creates a builder with every setting at its default value
+    pub fn builder() -> ConfigProfileBuilder {
+        ConfigProfileBuilder::new()
+    }
+
+    /// Loads a profile from TOML (see the [ConfigProfile] docs for the units used), and validates it
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(toml: &str) -> Result<Self, Error> {
+        let mut profile: Self = toml::from_str(toml).map_err(|error| Error::ConfigParse(error.to_string()))?;
+        profile.temperature_schedule.sort_by_key(|&(height, _)| height);
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    /// Width of a single extruded line (in nanometers): [ConfigProfile::extrusion_width] if it is set,
+    /// otherwise 1.2 times [ConfigProfile::nozzle_diameter]
+    pub fn extrusion_width(&self) -> u64 {
+        self.extrusion_width.unwrap_or_else(|| {
+            (self.nozzle_diameter as f64 * EXTRUSION_WIDTH_PER_NOZZLE_DIAMETER).round() as u64
+        })
+    }
+
+    /// Checks that every setting has a usable value
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.layer_height == 0 {
+            return Err(Error::InvalidConfig("layer_height must be greater than 0"));
+        }
+        if self.first_layer_height == Some(0) {
+            return Err(Error::InvalidConfig("first_layer_height must be greater than 0"));
+        }
+        if let Some(adaptive) = &self.adaptive_layer_height {
+            if adaptive.min_layer_height == 0 || adaptive.min_layer_height > adaptive.max_layer_height {
+                return Err(Error::InvalidConfig("adaptive layer heights must be greater than 0, with min_layer_height at most max_layer_height"));
+            }
+        }
+        if self.hotend_temperature == 0 || self.hotend_temperature > MAX_HOTEND_TEMPERATURE {
+            return Err(Error::InvalidConfig("hotend_temperature must be between 1 and 450"));
+        }
+        if self.bed_temperature > MAX_BED_TEMPERATURE {
+            return Err(Error::InvalidConfig("bed_temperature must be at most 150"));
+        }
+        if self.temperature_schedule.iter().any(|&(_, temperature)| temperature == 0 || temperature > MAX_HOTEND_TEMPERATURE) {
+            return Err(Error::InvalidConfig("temperature_schedule temperatures must be between 1 and 450"));
+        }
+        let mut heights: Vec<i64> = self.temperature_schedule.iter().map(|&(height, _)| height).collect();
+        heights.sort_unstable();
+        if heights.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(Error::InvalidConfig("temperature_schedule must not have a height repeated"));
+        }
+        if self.build_volume.as_ref().is_some_and(|volume| volume.x <= 0 || volume.y <= 0 || volume.z <= 0) {
+            return Err(Error::InvalidConfig("build_volume must be greater than 0 along every axis"));
+        }
+        if self.travel_speed == 0 {
+            return Err(Error::InvalidConfig("travel_speed must be greater than 0"));
+        }
+        if [self.outer_perimeter_speed, self.inner_perimeter_speed, self.infill_speed, self.first_layer_speed, self.bridge_speed]
+            .contains(&0) {
+            return Err(Error::InvalidConfig("print speeds must be greater than 0"));
+        }
+        if matches!(self.seam_position, SeamPosition::Aligned(degrees) if !degrees.is_finite()) {
+            return Err(Error::InvalidConfig("seam_position angle must be a finite number"));
+        }
+        if self.nozzle_diameter == 0 {
+            return Err(Error::InvalidConfig("nozzle_diameter must be greater than 0"));
+        }
+        if self.extrusion_width == Some(0) {
+            return Err(Error::InvalidConfig("extrusion_width must be greater than 0"));
+        }
+        if !(0.0..=1.0).contains(&self.infill_density) {
+            return Err(Error::InvalidConfig("infill_density must be between 0.0 and 1.0"));
+        }
+        if !(0.0..=1.0).contains(&self.ironing_flow) {
+            return Err(Error::InvalidConfig("ironing_flow must be between 0.0 and 1.0"));
+        }
+        if self.ironing_spacing == 0 {
+            return Err(Error::InvalidConfig("ironing_spacing must be greater than 0"));
+        }
+        if self.filament_diameter == 0 {
+            return Err(Error::InvalidConfig("filament_diameter must be greater than 0"));
+        }
+        if self.filament_density.is_nan() || self.filament_density <= 0.0 {
+            return Err(Error::InvalidConfig("filament_density must be greater than 0"));
+        }
+        if matches!(&self.retraction, Some(retraction) if retraction.speed == 0) {
+            return Err(Error::InvalidConfig("retraction speed must be greater than 0"));
+        }
+        if !(0.0..=1.0).contains(&self.fan_speed) {
+            return Err(Error::InvalidConfig("fan_speed must be between 0.0 and 1.0"));
+        }
+        if self.linear_advance.is_some_and(|k| !k.is_finite() || k < 0.0) {
+            return Err(Error::InvalidConfig("linear_advance must not be negative"));
+        }
+        if !(0.0..=1.0).contains(&self.bridge_fan_speed) {
+            return Err(Error::InvalidConfig("bridge_fan_speed must be between 0.0 and 1.0"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ConfigProfile {
+    /// Settings for a typical printer with a 0.4mm nozzle printing PLA
+    fn default() -> Self {
+        Self {
+            layer_height: 200_000,
+            first_layer_height: None,
+            adaptive_layer_height: None,
+            hotend_temperature: 200,
+            bed_temperature: 60,
+            temperature_schedule: Vec::new(),
+            travel_speed: 6000,
+            outer_perimeter_speed: 1800,
+            inner_perimeter_speed: 2700,
+            infill_speed: 3600,
+            first_layer_speed: 1200,
+            bridge_speed: 1800,
+            perimeters: 2,
+            external_perimeters_first: false,
+            seam_position: SeamPosition::Nearest,
+            skirt_loops: 1,
+            skirt_distance: 3_000_000,
+            brim_loops: 0,
+            raft_layers: 0,
+            raft_margin: 3_000_000,
+            raft_air_gap: 100_000,
+            top_layers: 4,
+            bottom_layers: 3,
+            nozzle_diameter: 400_000,
+            extrusion_width: None,
+            infill_density: 0.2,
+            ironing: false,
+            ironing_flow: 0.1,
+            ironing_spacing: 100_000,
+            vase_mode: false,
+            skip_empty_layers: true,
+            simplify_tolerance: Some(10_000),
+            min_segment_length: 0,
+            filament_diameter: 1_750_000,
+            filament_density: 1.24,
+            retraction: Some(RetractionConfig {
+                length: 1_000_000,
+                speed: 2100,
+                z_hop: 0,
+            }),
+            z_hop_only_when_crossing: false,
+            wipe_distance: 0,
+            coasting_distance: 0,
+            combing: true,
+            fan_speed: 1.0,
+            fan_first_layer_off: true,
+            bridge_fan_speed: 1.0,
+            build_volume: None,
+            firmware_flavor: FirmwareFlavor::Marlin,
+            acceleration: None,
+            jerk: None,
+            linear_advance: None,
+            relative_extrusion: false,
+            precision: Precision::default(),
+            comments: true,
+            filament_change_layers: Vec::new(),
+            start_gcode: None,
+            end_gcode: None,
+        }
+    }
+}
+
+/// Serializes a length in nanometers as millimeters, for `#[serde(with = "mm")]`
+#[cfg(feature = "serde")]
+mod mm {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use crate::geometry::NANOS_PER_MILLIMETER;
+
+    pub fn serialize<S: Serializer>(nanos: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(*nanos as f64 / NANOS_PER_MILLIMETER)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        let mm = f64::deserialize(deserializer)?;
+        if mm.is_finite() && mm >= 0.0 {
+            Ok((mm * NANOS_PER_MILLIMETER).round() as u64)
+        } else {
+            Err(serde::de::Error::custom("lengths must be non-negative numbers of millimeters"))
+        }
+    }
+}
+
+/// The same as [mm], for optional lengths
+#[cfg(feature = "serde")]
+mod mm_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(nanos: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match nanos {
+            Some(nanos) => super::mm::serialize(nanos, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+        /// Wrapper to deserialize the inside of the `Option` with [super::mm]
+        #[derive(Deserialize)]
+        struct Mm(#[serde(with = "super::mm")] u64);
+        Ok(Option::<Mm>::deserialize(deserializer)?.map(|Mm(nanos)| nanos))
+    }
+}
+
+/// Serializes [ConfigProfile::build_volume] as `[x, y, z]` in millimeters, like [mm]
+#[cfg(feature = "serde")]
+mod mm_volume {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use crate::geometry::Vector3D;
+
+    pub fn serialize<S: Serializer>(volume: &Option<Vector3D>, serializer: S) -> Result<S::Ok, S::Error> {
+        volume.as_ref().map(Vector3D::to_mm).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vector3D>, D::Error> {
+        match Option::<[f64; 3]>::deserialize(deserializer)? {
+            Some([x, y, z]) if [x, y, z].iter().all(|mm| mm.is_finite()) => Ok(Some(Vector3D::from_mm(x, y, z))),
+            Some(_) => Err(serde::de::Error::custom("build_volume must be numbers of millimeters")),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Serializes [ConfigProfile::temperature_schedule] with heights in millimeters, like [mm]
+#[cfg(feature = "serde")]
+mod mm_schedule {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use crate::geometry::NANOS_PER_MILLIMETER;
+
+    pub fn serialize<S: Serializer>(schedule: &[(i64, u32)], serializer: S) -> Result<S::Ok, S::Error> {
+        schedule.iter()
+            .map(|&(height, temperature)| (height as f64 / NANOS_PER_MILLIMETER, temperature))
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(i64, u32)>, D::Error> {
+        Vec::<(f64, u32)>::deserialize(deserializer)?.into_iter()
+            .map(|(mm, temperature)| if mm.is_finite() {
+                Ok(((mm * NANOS_PER_MILLIMETER).round() as i64, temperature))
+            } else {
+                Err(serde::de::Error::custom("heights must be numbers of millimeters"))
+            })
+            .collect()
+    }
+}
+
+/// Generates a [ConfigProfileBuilder] method that sets each of the given [ConfigProfile] fields
+macro_rules! setters {
+    ($($field:ident: $type:ty),* $(,)?) => {
+        $(
+            #[doc = concat!("Sets [ConfigProfile::", stringify!($field), "]")]
+            pub fn $field(mut self, $field: $type) -> Self {
+                self.profile.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+/// Builds a [ConfigProfile]. Created by [ConfigProfile::builder].
+pub struct ConfigProfileBuilder {
+    profile: ConfigProfile,
+}
+
+impl ConfigProfileBuilder {
+    fn new() -> Self {
+        Self {
+            profile: ConfigProfile::default(),
+        }
+    }
+
+    setters! {
+        layer_height: u64,
+        first_layer_height: Option<u64>,
+        adaptive_layer_height: Option<AdaptiveLayerHeight>,
+        hotend_temperature: u32,
+        bed_temperature: u32,
+        temperature_schedule: Vec<(i64, u32)>,
+        travel_speed: u32,
+        outer_perimeter_speed: u32,
+        inner_perimeter_speed: u32,
+        infill_speed: u32,
+        first_layer_speed: u32,
+        bridge_speed: u32,
+        perimeters: u32,
+        external_perimeters_first: bool,
+        seam_position: SeamPosition,
+        skirt_loops: u32,
+        skirt_distance: u64,
+        brim_loops: u32,
+        raft_layers: u32,
+        raft_margin: u64,
+        raft_air_gap: u64,
+        top_layers: u32,
+        bottom_layers: u32,
+        nozzle_diameter: u64,
+        extrusion_width: Option<u64>,
+        infill_density: f32,
+        ironing: bool,
+        ironing_flow: f32,
+        ironing_spacing: u64,
+        vase_mode: bool,
+        skip_empty_layers: bool,
+        simplify_tolerance: Option<u64>,
+        min_segment_length: u64,
+        filament_diameter: u64,
+        filament_density: f64,
+        retraction: Option<RetractionConfig>,
+        z_hop_only_when_crossing: bool,
+        wipe_distance: u64,
+        coasting_distance: u64,
+        combing: bool,
+        fan_speed: f32,
+        fan_first_layer_off: bool,
+        bridge_fan_speed: f32,
+        build_volume: Option<Vector3D>,
+        firmware_flavor: FirmwareFlavor,
+        acceleration: Option<AccelerationConfig>,
+        jerk: Option<JerkConfig>,
+        linear_advance: Option<f32>,
+        relative_extrusion: bool,
+        precision: Precision,
+        comments: bool,
+        filament_change_layers: Vec<usize>,
+        start_gcode: Option<String>,
+        end_gcode: Option<String>,
+    }
+
+    /// Validates the settings (see [ConfigProfile::validate]) and builds the profile
+    pub fn build(mut self) -> Result<ConfigProfile, Error> {
+        self.profile.temperature_schedule.sort_by_key(|&(height, _)| height);
+        self.profile.validate()?;
+        Ok(self.profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature_schedule_is_sorted_by_height() {
+        let profile = ConfigProfile::builder()
+            .temperature_schedule(vec![(10_000_000, 200), (2_000_000, 220), (6_000_000, 210)])
+            .build()
+            .unwrap();
+        assert_eq!(profile.temperature_schedule, [(2_000_000, 220), (6_000_000, 210), (10_000_000, 200)]);
+
+        let repeated = ConfigProfile::builder().temperature_schedule(vec![(5_000_000, 200), (1_000_000, 210), (5_000_000, 220)]).build();
+        assert!(matches!(repeated, Err(Error::InvalidConfig(_))));
+    }
+}
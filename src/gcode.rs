@@ -1,17 +1,13 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::borrow::Cow;
-use crate::slice::Slice;
-use crate::ConfigProfile;
-
-#[derive(PartialEq, Eq, Hash, Copy, Clone)]
-pub enum Axis {
-    X,
-    Y,
-    Z,
-}
+use crate::slice::{Slice, SliceIsland};
+use crate::geometry::{self, Polygon, Vector2D, NANOS_PER_MILLIMETER};
+use crate::combing::CombingBoundary;
+pub use crate::geometry::Axis;
+use crate::{ConfigProfile, Error};
 
 impl Axis {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             Axis::X => "X",
             Axis::Y => "Y",
@@ -20,7 +16,81 @@ impl Axis {
     }
 }
 
-/// A GCode instruction
+/// The printer firmware that G-code is generated for. Firmwares mostly understand the same
+/// codes, but differ in a few places (mostly temperature waits and fan control).
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FirmwareFlavor {
+    Marlin,
+    RepRapFirmware,
+    /// Klipper understands the same G-code as Marlin, apart from its own extended commands
+    Klipper,
+}
+
+/// How many decimal places lengths (in millimeters) are written to G-code with. Fewer places make smaller files,
+/// and some firmwares only read a limited number of them. More than 6 is the same as 6, since lengths are
+/// whole nanometers.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Precision {
+    /// Decimal places of X, Y, and Z coordinates (including arc centers)
+    pub coordinates: u8,
+    /// Decimal places of extruder (`E`) lengths
+    pub extrusion: u8,
+}
+
+impl Default for Precision {
+    /// 3 decimal places (micrometers) for coordinates, and 5 for extrusion
+    fn default() -> Self {
+        Self {
+            coordinates: 3,
+            extrusion: 5,
+        }
+    }
+}
+
+/// Where each perimeter loop starts and ends, which leaves a visible seam on the surface of the print
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SeamPosition {
+    /// Whichever vertex is closest to where the nozzle already is, which makes travel moves shortest
+    Nearest,
+    /// The vertex that is furthest in the given direction, as an angle in degrees counterclockwise from the
+    /// +X axis, so that seams of different layers line up
+    Aligned(f32),
+    /// A random vertex, so that seams are spread out over the surface instead of forming a line. The
+    /// randomness is seeded the same way every time, so the same model always gets the same G-code.
+    Random,
+}
+
+/// Converts a length in nanometers to millimeters for writing to G-code
+fn nanos_to_mm(nanos: i64) -> f64 {
+    nanos as f64 / NANOS_PER_MILLIMETER
+}
+
+/// Writes a length in nanometers as millimeters, rounded to at most `decimals` decimal places (halves away from
+/// zero) with any trailing zeros left out. Rounding is done on the integer, so no floating point error creeps in.
+fn format_mm(nanos: i64, decimals: u8) -> String {
+    // nanometers are the most precision there is
+    let decimals = decimals.min(6) as u32;
+    let unit = 10_u64.pow(6 - decimals);
+    let rounded = (nanos.unsigned_abs() + unit / 2) / unit;
+    let scale = 10_u64.pow(decimals);
+    let sign = if nanos < 0 && rounded != 0 { "-" } else { "" };
+    let (whole, fraction) = (rounded / scale, rounded % scale);
+    if fraction == 0 {
+        format!("{}{}", sign, whole)
+    } else {
+        let digits = format!("{:0width$}", fraction, width = decimals as usize);
+        format!("{}{}.{}", sign, whole, digits.trim_end_matches('0'))
+    }
+}
+
+/// A GCode instruction.
+///
+/// Positions and distances are in nanometers, like everywhere else in the library. They are converted
+/// to millimeters when the command is written as G-code. Speeds are feedrates in mm/min (the `F` word).
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     /// Homes each axis in the PerAxis. If no axes are specified, homes all axes.
     Home(PerAxis<()>),
@@ -36,17 +106,79 @@ pub enum Command {
     ExtrudeMove {
         amounts: PerAxis<i64>,
         speed: u32,
-        /// How much filament to extrude during the move
-        extrude_len: u32,
+        /// The `E` word: how far to move the extruder. Like the other axes this is either a
+        /// position or a relative amount depending on the positioning mode, so with absolute positioning
+        /// it is the total length of filament fed since the extruder position was last set.
+        ///
+        /// After `SetRelativeExtrusion`, the extruder is always positioned relatively (regardless of
+        /// `SetAbsolutePositioning`), so this is the length of filament fed during just this move.
+        extrude_len: i64,
+    },
+    /// Moves along a circular arc in the XY plane
+    ArcMove {
+        /// Where the arc ends
+        end: PerAxis<i64>,
+        /// X/Y offset from the start of the arc to the center of its circle (the `I` and `J` words)
+        center_offset: (i64, i64),
+        clockwise: bool,
+        speed: u32,
+        /// The `E` word, with the same meaning as in `ExtrudeMove`. `None` for an arc that doesn't extrude.
+        extrude_len: Option<i64>,
     },
     SetPosition(PerAxis<i64>),
     SetExtruderPosition(i64),
+    /// Makes the `E` word of later moves an absolute extruder position (the default), unless
+    /// `SetRelativePositioning` is in effect
+    SetAbsoluteExtrusion,
+    /// Makes the `E` word of later moves a relative amount of filament, even with `SetAbsolutePositioning`
+    SetRelativeExtrusion,
+    /// Sets the hotend temperature without waiting for it to be reached
+    SetTemp(u32),
     BlockingSetTemp(u32),
+    SetBedTemp(u32),
+    BlockingSetBedTemp(u32),
+    /// Sets the part cooling fan speed, from 0 (off) to 255 (full speed)
+    SetFanSpeed(u8),
+    /// Sets the acceleration of extrusion and travel moves (in mm/s^2)
+    SetAcceleration {
+        print: u32,
+        travel: u32,
+    },
+    /// Sets the jerk limit of each axis in the PerAxis (in mm/s)
+    SetJerk(PerAxis<u32>),
+    /// Sets the linear advance (Marlin) or pressure advance (Klipper, RepRapFirmware) factor: how much
+    /// extra filament to push to make up for the pressure changing in the nozzle as the print speed changes.
+    /// 0 disables it.
+    SetLinearAdvance(f32),
+    /// Updates the print progress shown on the printer's display
+    SetProgress {
+        percent: u8,
+        /// Estimated time left (in minutes)
+        remaining_min: u16,
+    },
+    FanOff,
+    /// Pauses the print for the filament to be swapped
+    FilamentChange,
+    /// Switches to another extruder, numbered from 0
+    SelectTool(u8),
+    /// G-code that is output verbatim
+    Raw(String),
+    /// A comment, which the printer ignores. Written as `; ` followed by the text.
+    Comment(String),
 }
 
 impl Command {
-    fn as_code(&self) -> Cow<'static, str> {
+    /// The G-code for this command, as understood by firmware of the given `flavor`.
+    /// Some commands produce more than one line.
+    pub fn as_code(&self, flavor: FirmwareFlavor) -> Cow<'static, str> {
+        self.as_code_with_precision(flavor, Precision::default())
+    }
+
+    /// Like [Command::as_code], with lengths rounded to the number of decimal places given by `precision`
+    pub fn as_code_with_precision(&self, flavor: FirmwareFlavor, precision: Precision) -> Cow<'static, str> {
         use Command::*;
+        let coordinate = |nanos: i64| format_mm(nanos, precision.coordinates);
+        let extrusion = |nanos: i64| format_mm(nanos, precision.extrusion);
         match self {
             Home(axes) => format!(
                 "G28{}",
@@ -59,39 +191,94 @@ impl Command {
             Move { amounts, speed } => format!(
                 "G1 {}F{}",
                 amounts.entries()
-                    .map(|(axis, amnt)| format!("{}{} ", axis.to_str(), amnt))
+                    .map(|(axis, amnt)| format!("{}{} ", axis.to_str(), coordinate(*amnt)))
                     .collect::<String>(),
                 speed,
             ).into(),
             ExtrudeMove { amounts, speed, extrude_len } => format!(
                 "G1 {}E{} F{}",
                 amounts.entries()
-                    .map(|(axis, amnt)| format!("{}{} ", axis.to_str(), amnt))
+                    .map(|(axis, amnt)| format!("{}{} ", axis.to_str(), coordinate(*amnt)))
                     .collect::<String>(),
-                extrude_len,
+                extrusion(*extrude_len),
+                speed,
+            ).into(),
+            ArcMove { end, center_offset: (i, j), clockwise, speed, extrude_len } => format!(
+                "G{} {}I{} J{} {}F{}",
+                if *clockwise { 2 } else { 3 },
+                end.entries()
+                    .map(|(axis, amnt)| format!("{}{} ", axis.to_str(), coordinate(*amnt)))
+                    .collect::<String>(),
+                coordinate(*i),
+                coordinate(*j),
+                extrude_len
+                    .map(|len| format!("E{} ", extrusion(len)))
+                    .unwrap_or_default(),
                 speed,
             ).into(),
             SetPosition(pozs) => format!(
                 "G92{}",
                 pozs.entries()
-                    .map(|(axis, pos)| format!(" {}{}", axis.to_str(), pos))
+                    .map(|(axis, pos)| format!(" {}{}", axis.to_str(), coordinate(*pos)))
                     .collect::<String>(),
             ).into(),
-            SetExtruderPosition(pos) => format!("G92 E{}", pos).into(),
-            BlockingSetTemp(temp) => format!("M109 S{}", temp).into(),
+            SetExtruderPosition(pos) => format!("G92 E{}", extrusion(*pos)).into(),
+            SetAbsoluteExtrusion => "M82".into(),
+            SetRelativeExtrusion => "M83".into(),
+            SetTemp(temp) => format!("M104 S{}", temp).into(),
+            BlockingSetTemp(temp) => match flavor {
+                FirmwareFlavor::Marlin | FirmwareFlavor::Klipper => format!("M109 S{}", temp).into(),
+                // RepRapFirmware deprecates M109 in favor of setting the temperature and then waiting with M116
+                FirmwareFlavor::RepRapFirmware => format!("M104 S{}\nM116 P0", temp).into(),
+            },
+            SetBedTemp(temp) => format!("M140 S{}", temp).into(),
+            BlockingSetBedTemp(temp) => format!("M190 S{}", temp).into(),
+            SetFanSpeed(speed) => format!("M106 S{}", speed).into(),
+            SetAcceleration { print, travel } => format!("M204 P{} T{}", print, travel).into(),
+            SetJerk(limits) => match flavor {
+                FirmwareFlavor::Marlin | FirmwareFlavor::Klipper => format!(
+                    "M205{}",
+                    limits.entries()
+                        .map(|(axis, limit)| format!(" {}{}", axis.to_str(), limit))
+                        .collect::<String>(),
+                ).into(),
+                // RepRapFirmware sets jerk with M566, in mm/min
+                FirmwareFlavor::RepRapFirmware => format!(
+                    "M566{}",
+                    limits.entries()
+                        .map(|(axis, limit)| format!(" {}{}", axis.to_str(), limit * 60))
+                        .collect::<String>(),
+                ).into(),
+            },
+            SetLinearAdvance(k) => match flavor {
+                FirmwareFlavor::Marlin => format!("M900 K{}", k).into(),
+                FirmwareFlavor::Klipper => format!("SET_PRESSURE_ADVANCE ADVANCE={}", k).into(),
+                FirmwareFlavor::RepRapFirmware => format!("M572 D0 S{}", k).into(),
+            },
+            SetProgress { percent, remaining_min } => format!("M73 P{} R{}", percent, remaining_min).into(),
+            FanOff => match flavor {
+                FirmwareFlavor::Marlin | FirmwareFlavor::Klipper => "M107".into(),
+                // M107 is deprecated in RepRapFirmware
+                FirmwareFlavor::RepRapFirmware => "M106 S0".into(),
+            },
+            FilamentChange => "M600".into(),
+            SelectTool(tool) => format!("T{}", tool).into(),
+            Raw(code) => code.clone().into(),
+            Comment(text) => format!("; {}", text).into(),
         }
     }
 }
 
 /// Holds a value of type `T` for each axis
+#[derive(Debug, Clone, PartialEq)]
 pub struct PerAxis<T> {
-    map: HashMap<Axis, T>,
+    map: BTreeMap<Axis, T>,
 }
 
 impl<T> PerAxis<T> {
     pub fn none() -> Self {
         Self {
-            map: HashMap::new(),
+            map: BTreeMap::new(),
         }
     }
 
@@ -100,80 +287,1174 @@ impl<T> PerAxis<T> {
         self
     }
 
+    /// Iterates over the axes that have a value, always in X, Y, Z order
     fn entries(&self) -> impl Iterator<Item=(&Axis, &T)> {
         self.map.iter()
     }
 }
 
-pub fn slices_to_gcode(config: &ConfigProfile, slices: &[Slice]) -> String {
-    let mut gcoder = GCodeBuilder::new(config);
+/// Parses G-code (like the output of [slices_to_gcode]) back into commands, so that it can be checked
+/// or processed further. This is the reverse of [Command::as_code] for either firmware flavor, except that
+/// RepRapFirmware's `M106 S0` is read as `SetFanSpeed(0)` rather than `FanOff`.
+///
+/// Lines that aren't one of the commands generated by this library are returned as [Command::Raw], one
+/// per line. A move without an `F` word uses the feedrate of the previous move, like on a printer.
+/// Returns [Error::GCodeParse] with the (1-based) line number if a recognized command has a malformed
+/// or missing argument.
+pub fn parse(text: &str) -> Result<Vec<Command>, Error> {
+    let mut commands = Vec::new();
+    let mut speed = 0;
+    for (index, line) in text.lines().enumerate() {
+        let error = || Error::GCodeParse(index + 1);
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix(';') {
+            commands.push(Command::Comment(comment.strip_prefix(' ').unwrap_or(comment).to_string()));
+            continue;
+        }
+        // ignore comments at the end of the line
+        let code = line.split(';').next().unwrap().trim();
+        let mut words = code.split_whitespace();
+        let Some(name) = words.next() else {
+            continue;
+        };
+        let name = name.to_ascii_uppercase();
+        let words: Vec<&str> = words.collect();
+        // words are parsed when a command uses them, so that unknown commands with other kinds of
+        // arguments are kept as Raw
+        let args: BTreeMap<char, &str> = words.iter()
+            .map(|word| {
+                let mut chars = word.chars();
+                (chars.next().unwrap().to_ascii_uppercase(), chars.as_str())
+            })
+            .collect();
+
+        // the value of an argument, which has to be present
+        let arg = |letter: char| args.get(&letter)
+            .and_then(|value| value.parse::<f64>().ok())
+            .ok_or_else(error);
+        let length = |letter: char| arg(letter).map(|mm| (mm * NANOS_PER_MILLIMETER).round() as i64);
+        let integer = |letter: char| arg(letter).map(|value| value.round() as u32);
+        let axes = |args: &BTreeMap<char, &str>| -> Result<PerAxis<i64>, Error> {
+            let mut amounts = PerAxis::none();
+            for (letter, axis) in [('X', Axis::X), ('Y', Axis::Y), ('Z', Axis::Z)] {
+                if args.contains_key(&letter) {
+                    amounts = amounts.set(axis, length(letter)?);
+                }
+            }
+            Ok(amounts)
+        };
+        if name.starts_with('G') && args.contains_key(&'F') {
+            speed = integer('F')?;
+        }
+
+        let command = match name.as_str() {
+            "G0" | "G1" if args.contains_key(&'E') => Command::ExtrudeMove {
+                amounts: axes(&args)?,
+                speed,
+                extrude_len: length('E')?,
+            },
+            "G0" | "G1" => Command::Move { amounts: axes(&args)?, speed },
+            "G2" | "G3" => Command::ArcMove {
+                end: axes(&args)?,
+                center_offset: (length('I')?, length('J')?),
+                clockwise: name == "G2",
+                speed,
+                extrude_len: if args.contains_key(&'E') { Some(length('E')?) } else { None },
+            },
+            "G28" => {
+                let mut homed = PerAxis::none();
+                for (letter, axis) in [('X', Axis::X), ('Y', Axis::Y), ('Z', Axis::Z)] {
+                    if args.contains_key(&letter) {
+                        homed = homed.set(axis, ());
+                    }
+                }
+                Command::Home(homed)
+            },
+            "G90" => Command::SetAbsolutePositioning,
+            "G91" => Command::SetRelativePositioning,
+            "G92" if args.len() == 1 && args.contains_key(&'E') => Command::SetExtruderPosition(length('E')?),
+            "G92" => Command::SetPosition(axes(&args)?),
+            "M82" => Command::SetAbsoluteExtrusion,
+            "M83" => Command::SetRelativeExtrusion,
+            "M73" => Command::SetProgress {
+                percent: integer('P')?.min(u8::MAX as u32) as u8,
+                remaining_min: integer('R')?.min(u16::MAX as u32) as u16,
+            },
+            "M104" => Command::SetTemp(integer('S')?),
+            "M109" => Command::BlockingSetTemp(integer('S')?),
+            // RepRapFirmware waits for the temperature set by the previous line
+            "M116" if matches!(commands.last(), Some(Command::SetTemp(_))) => {
+                let Some(Command::SetTemp(temp)) = commands.pop() else { unreachable!() };
+                Command::BlockingSetTemp(temp)
+            },
+            "M140" => Command::SetBedTemp(integer('S')?),
+            "M190" => Command::BlockingSetBedTemp(integer('S')?),
+            "M106" => Command::SetFanSpeed(integer('S')?.min(u8::MAX as u32) as u8),
+            "M107" => Command::FanOff,
+            "M600" => Command::FilamentChange,
+            "M900" => Command::SetLinearAdvance(arg('K')? as f32),
+            "M572" => Command::SetLinearAdvance(arg('S')? as f32),
+            "SET_PRESSURE_ADVANCE" => {
+                let advance = words.iter()
+                    .find_map(|word| word.to_ascii_uppercase().strip_prefix("ADVANCE=")?.parse::<f32>().ok());
+                Command::SetLinearAdvance(advance.ok_or_else(error)?)
+            },
+            "M204" => Command::SetAcceleration { print: integer('P')?, travel: integer('T')? },
+            "M205" | "M566" => {
+                // RepRapFirmware's limits are in mm/min
+                let divisor = if name == "M566" { 60.0 } else { 1.0 };
+                let mut limits = PerAxis::none();
+                for (letter, axis) in [('X', Axis::X), ('Y', Axis::Y), ('Z', Axis::Z)] {
+                    if args.contains_key(&letter) {
+                        limits = limits.set(axis, (arg(letter)? / divisor).round() as u32);
+                    }
+                }
+                Command::SetJerk(limits)
+            },
+            _ => match name.strip_prefix('T').and_then(|tool| tool.parse::<u8>().ok()) {
+                Some(tool) if words.is_empty() => Command::SelectTool(tool),
+                _ => Command::Raw(line.to_string()),
+            },
+        };
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+/// Fewest commands to write G-code for on multiple threads. Fewer than this are quicker to write on one thread.
+const PARALLEL_RENDER_THRESHOLD: usize = 50_000;
+
+/// Estimated time it takes to home the printer (in seconds)
+const HOME_SECONDS: f64 = 15.0;
+/// Estimated time spent waiting for a heater to reach its temperature (in seconds)
+const HEAT_WAIT_SECONDS: f64 = 60.0;
+
+/// Generated G-code along with estimates about printing it
+pub struct GCodeOutput {
+    pub gcode: String,
+    /// Roughly how long the print will take (in seconds)
+    pub estimated_seconds: f64,
+    /// Length of filament used (in millimeters)
+    pub filament_mm: f64,
+    /// Volume of filament used (in cubic millimeters)
+    pub filament_mm3: f64,
+    /// Mass of filament used (in grams), based on [ConfigProfile::filament_density]
+    pub filament_grams: f64,
+}
+
+/// Generates G-code to print `slices`.
+///
+/// Returns [Error::CoordinateOverflow] if the model is so tall or uses so much filament that positions
+/// don't fit in an `i64` (of nanometers).
+pub fn slices_to_gcode(config: &ConfigProfile, slices: &[Slice]) -> Result<String, Error> {
+    Ok(slices_to_gcode_with_stats(config, slices)?.gcode)
+}
+
+/// Like [slices_to_gcode], but also estimates how long the print will take and how much filament it uses
+pub fn slices_to_gcode_with_stats(config: &ConfigProfile, slices: &[Slice]) -> Result<GCodeOutput, Error> {
+    slices_to_gcode_with_layer_callback(config, slices, |_, _| Vec::new())
+}
+
+/// Like [slices_to_gcode_with_stats], but calls `callback` at the start of each layer with the index of the
+/// layer and the height of its top (in nanometers). The commands it returns are inserted there, before anything
+/// in the layer is printed, which allows things like pausing for a color change or running a custom macro
+/// (with [Command::Raw]) at specific layers.
+pub fn slices_to_gcode_with_layer_callback(
+    config: &ConfigProfile,
+    slices: &[Slice],
+    callback: impl FnMut(usize, i64) -> Vec<Command>,
+) -> Result<GCodeOutput, Error> {
+    let mut gcoder = GCodeBuilder::new(config, slices.len());
+    gcoder.add_layer_callback(callback);
     gcoder.add_starting_gcode();
     for slice in slices {
-        gcoder.add_slice(slice);
+        gcoder.add_slice(slice)?;
     }
-    gcoder.generate_gcode()
+    gcoder.add_ending_gcode();
+    gcoder.fill_in_remaining_time();
+    let filament_mm3 = gcoder.filament_used_mm3();
+    let mut output = GCodeOutput {
+        gcode: String::new(),
+        estimated_seconds: gcoder.estimated_seconds(),
+        filament_mm: gcoder.filament_used_mm(),
+        filament_mm3,
+        // g/cm^3 is the same as mg/mm^3
+        filament_grams: filament_mm3 * config.filament_density / 1000.0,
+    };
+    gcoder.add_header(&output);
+    output.gcode = gcoder.generate_gcode();
+    Ok(output)
 }
 
+/// Index of the vertex of `polygon` that is closest to `point`
+fn nearest_vertex(polygon: &Polygon, point: &Vector2D) -> usize {
+    polygon.vertices().iter()
+        .map(|vertex| point.distance_to(vertex))
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+        .unwrap()
+}
+
+/// Index of the vertex of `polygon` that is furthest in the direction `degrees` counterclockwise from +X
+fn furthest_vertex(polygon: &Polygon, degrees: f32) -> usize {
+    let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+    polygon.vertices().iter()
+        .map(|vertex| vertex.x as f64 * cos + vertex.y as f64 * sin)
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+        .unwrap()
+}
+
+/// The vertices to move through to go around a loop from `vertices[0]` (and back to it), leaving out vertices
+/// less than `min_length` from the one before them, other than the last one. Each vertex is given with the
+/// length of the path to it from the one before, including the parts through the vertices that were left out.
+fn merge_short_edges(vertices: &[Vector2D], min_length: f64) -> Vec<(&Vector2D, f64)> {
+    let mut merged = Vec::new();
+    let mut previous = &vertices[0];
+    let mut path_length = 0.0;
+    for (index, edge) in vertices.windows(2).enumerate() {
+        path_length += edge[0].distance_to(&edge[1]);
+        let is_last = index == vertices.len() - 2;
+        if is_last || previous.distance_to(&edge[1]) >= min_length {
+            merged.push((&edge[1], path_length));
+            previous = &edge[1];
+            path_length = 0.0;
+        }
+    }
+    merged
+}
+
+/// See [GCodeBuilder::add_layer_callback]
+type LayerCallback<'a> = dyn FnMut(usize, i64) -> Vec<Command> + 'a;
+
 struct GCodeBuilder<'a> {
     commands: Vec<Command>,
     config: &'a ConfigProfile,
     top_height: i64,
+    /// Thickness of the slice currently being added
+    layer_thickness: u64,
+    /// Where the toolhead is in the XY plane
+    position: Vector2D,
+    /// The end of the path printed since the last travel move, covering at least
+    /// [ConfigProfile::wipe_distance] if it is that long. The last point is `position`.
+    printed_path: Vec<Vector2D>,
+    /// Total length of filament extruded so far (in nanometers)
+    extruder_position: i64,
+    /// Whether the filament is currently retracted
+    retracted: bool,
+    /// Whether the nozzle is currently lifted by [RetractionConfig::z_hop](crate::RetractionConfig::z_hop)
+    lifted: bool,
+    /// The outlines and holes of the islands of the current slice, which travel moves can only cross lifted
+    /// (see [ConfigProfile::z_hop_only_when_crossing]). Empty unless that is enabled.
+    layer_outlines: Vec<Polygon>,
+    /// Index of the slice currently being added
+    layer_index: usize,
+    /// Total number of slices that will be added
+    layer_count: usize,
+    /// The hotend temperature most recently set
+    hotend_temperature: u32,
+    /// The fan speed most recently set, from 0 (off) to 255 (full speed)
+    fan_speed: u8,
+    /// The extruder currently selected, which starts out as 0 (see [SliceIsland::tool])
+    tool: u8,
+    /// How much to extrude, as a fraction of the normal amount (see [GCodeBuilder::filament_length])
+    flow: f64,
+    /// State of the random number generator for [SeamPosition::Random]
+    seam_random_state: u64,
+    /// The island being printed, if travel moves should stay inside it (see [ConfigProfile::combing])
+    combing: Option<CombingBoundary>,
+    /// Called at the start of each layer (see [GCodeBuilder::add_layer_callback])
+    layer_callbacks: Vec<Box<LayerCallback<'a>>>,
 }
 
 impl<'a> GCodeBuilder<'a> {
-    fn new(config: &'a ConfigProfile) -> Self {
+    fn new(config: &'a ConfigProfile, layer_count: usize) -> Self {
         Self {
             commands: Vec::new(),
             config,
             top_height: 0,
+            layer_thickness: 0,
+            position: Vector2D::new(0, 0),
+            printed_path: Vec::new(),
+            extruder_position: 0,
+            retracted: false,
+            lifted: false,
+            layer_outlines: Vec::new(),
+            layer_index: 0,
+            layer_count,
+            hotend_temperature: config.hotend_temperature,
+            fan_speed: 0,
+            tool: 0,
+            flow: 1.0,
+            seam_random_state: 0x2545_f491_4f6c_dd1d,
+            combing: None,
+            layer_callbacks: Vec::new(),
         }
     }
 
+    /// Calls `callback` at the start of each layer with the layer index and the height of the layer's top,
+    /// and inserts the commands it returns
+    fn add_layer_callback(&mut self, callback: impl FnMut(usize, i64) -> Vec<Command> + 'a) {
+        self.layer_callbacks.push(Box::new(callback));
+    }
+
     /// Insert a raw command
     fn command(&mut self, cmd: Command) {
         self.commands.push(cmd);
     }
 
+    /// Insert a comment, unless comments are disabled by [ConfigProfile::comments]
+    fn comment(&mut self, text: String) {
+        if self.config.comments {
+            self.command(Command::Comment(text));
+        }
+    }
+
+    /// Inserts comments summarizing the settings and the estimates in `output` before all of the other
+    /// commands
+    fn add_header(&mut self, output: &GCodeOutput) {
+        if !self.config.comments {
+            return;
+        }
+        let seconds = output.estimated_seconds.round() as u64;
+        let header = [
+            "generated by ddd".to_string(),
+            format!("layer height: {} mm", nanos_to_mm(self.config.layer_height as i64)),
+            format!("extrusion width: {} mm", nanos_to_mm(self.config.extrusion_width() as i64)),
+            format!("perimeters: {}", self.config.perimeters),
+            format!("infill density: {}%", (self.config.infill_density * 100.0).round()),
+            format!("temperatures: {} C hotend, {} C bed", self.config.hotend_temperature, self.config.bed_temperature),
+            format!("estimated printing time: {}h {}m {}s", seconds / 3600, seconds / 60 % 60, seconds % 60),
+            format!("filament used: {:.2} mm, {:.2} g", output.filament_mm, output.filament_grams),
+        ];
+        self.commands.splice(0..0, header.into_iter().map(Command::Comment));
+    }
+
     fn add_starting_gcode(&mut self) {
+        let heated_bed = self.config.bed_temperature > 0;
+        // start heating while homing, and let the hotend heat up while waiting for the bed
+        if heated_bed {
+            self.command(Command::SetBedTemp(self.config.bed_temperature));
+        }
+        self.command(Command::SetTemp(self.config.hotend_temperature));
         self.command(Command::SetAbsolutePositioning);
+        if let Some(acceleration) = &self.config.acceleration {
+            self.command(Command::SetAcceleration { print: acceleration.print, travel: acceleration.travel });
+        }
+        if let Some(jerk) = &self.config.jerk {
+            self.command(Command::SetJerk(PerAxis::none()
+                .set(Axis::X, jerk.x)
+                .set(Axis::Y, jerk.y)
+                .set(Axis::Z, jerk.z)));
+        }
+        if let Some(k) = self.config.linear_advance {
+            self.command(Command::SetLinearAdvance(k));
+        }
+        if self.config.relative_extrusion {
+            self.command(Command::SetRelativeExtrusion);
+        }
         self.command(Command::Home(PerAxis::none()));
+        self.command(Command::SetExtruderPosition(0));
+        self.command(Command::FanOff);
+        if heated_bed {
+            self.command(Command::BlockingSetBedTemp(self.config.bed_temperature));
+        }
         self.command(Command::BlockingSetTemp(self.config.hotend_temperature));
+        if let Some(start_gcode) = &self.config.start_gcode {
+            self.command(Command::Raw(start_gcode.clone()));
+        }
+    }
+
+    fn add_ending_gcode(&mut self) {
+        self.command(Command::SetProgress { percent: 100, remaining_min: 0 });
+        if let Some(end_gcode) = &self.config.end_gcode {
+            self.command(Command::Raw(end_gcode.clone()));
+        }
     }
 
     /// Adds gcode to print the given slice
-    fn add_slice(&mut self, slice: &Slice) {
-        //FIXME: don't hardcode nm/mm conversion (200000)
+    fn add_slice(&mut self, slice: &Slice) -> Result<(), Error> {
+        // in vase mode, every layer after the first one of the model (above the raft, if there is one) spirals up
+        // from the top of the one below it
+        let spiral = self.config.vase_mode && self.layer_index > self.first_model_layer();
+        let bottom = self.top_height;
+        self.top_height = slice.air_gap().checked_add(slice.thickness())
+            .and_then(|height| i64::try_from(height).ok())
+            .and_then(|thickness| self.top_height.checked_add(thickness))
+            .ok_or(Error::CoordinateOverflow)?;
+        self.layer_thickness = slice.thickness();
+        self.comment(format!("LAYER:{} Z={}", self.layer_index, nanos_to_mm(self.top_height)));
+        // the remaining time is filled in once all the commands exist
+        self.command(Command::SetProgress {
+            percent: (self.layer_index * 100 / self.layer_count) as u8,
+            remaining_min: 0,
+        });
+        if !spiral {
+            self.retract();
+            // increment z height
+            self.move_z()?;
+        }
+        if self.config.z_hop_only_when_crossing {
+            self.layer_outlines = slice.islands().iter()
+                .flat_map(|island| std::iter::once(island.outline()).chain(island.holes()))
+                .cloned()
+                .collect();
+        }
+
+        if let Some(&(_, temperature)) = self.config.temperature_schedule.iter()
+            .filter(|(height, _)| *height <= self.top_height)
+            .max_by_key(|(height, _)| *height)
+        {
+            if temperature != self.hotend_temperature {
+                self.command(Command::SetTemp(temperature));
+                self.hotend_temperature = temperature;
+            }
+        }
+
+        // a raft is printed with the fan off too
+        let fan_layer = if self.config.fan_first_layer_off { self.first_model_layer() + 1 } else { 0 };
+        if self.layer_index == fan_layer {
+            self.set_fan_speed(self.config.fan_speed);
+        }
+
+        if self.config.filament_change_layers.contains(&self.layer_index) {
+            self.command(Command::FilamentChange);
+        }
+        for callback in &mut self.layer_callbacks {
+            self.commands.extend(callback(self.layer_index, self.top_height));
+        }
+
+        if !slice.skirt().is_empty() {
+            self.comment("TYPE:SKIRT".to_string());
+        }
+        for polygon in slice.skirt() {
+            self.add_loop(polygon, self.config.first_layer_speed)?;
+        }
+
+        // greedily print whichever island can be reached with the shortest travel next, finishing the islands of the
+        // current tool before changing to another one
+        let mut remaining: Vec<&SliceIsland> = slice.islands().iter().collect();
+        while !remaining.is_empty() {
+            let same_tool = remaining.iter().any(|island| island.tool() == self.tool);
+            let (index, _) = remaining.iter()
+                .enumerate()
+                .filter(|(_, island)| !same_tool || island.tool() == self.tool)
+                .map(|(index, island)| (index, self.entry_distance(island)))
+                .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                .unwrap();
+            let island = remaining.remove(index);
+
+            // travelling from another island crosses open space
+            if !spiral {
+                self.retract();
+            }
+            if island.tool() != self.tool {
+                self.command(Command::SelectTool(island.tool()));
+                self.tool = island.tool();
+            }
+            if !island.brim().is_empty() {
+                // brims are marked as part of the skirt, like other slicers do
+                self.comment("TYPE:SKIRT".to_string());
+            }
+            for polygon in island.brim() {
+                self.add_loop(polygon, self.config.first_layer_speed)?;
+            }
+            // the brim is outside of the island, but everything else can be reached from inside
+            if self.config.combing {
+                self.combing = Some(CombingBoundary::new(island));
+            }
+            let mut perimeters: Vec<(usize, &Vec<Polygon>)> = island.perimeters().iter().enumerate().collect();
+            if !self.config.external_perimeters_first {
+                perimeters.reverse();
+            }
+            for (index, perimeter) in perimeters {
+                let (feature, speed) = if index == 0 {
+                    ("WALL-OUTER", self.config.outer_perimeter_speed)
+                } else {
+                    ("WALL-INNER", self.config.inner_perimeter_speed)
+                };
+                self.comment(format!("TYPE:{}", feature));
+                let speed = self.feature_speed(speed);
+                for polygon in perimeter {
+                    if spiral {
+                        self.add_spiral_loop(polygon, bottom, speed)?;
+                    } else {
+                        let start = self.seam_vertex(polygon);
+                        self.add_loop_from(polygon, start, speed)?;
+                    }
+                }
+            }
+            let speed = self.feature_speed(self.config.infill_speed);
+            for (feature, lines) in [("FILL", island.infill()), ("SKIN", island.solid_infill())] {
+                if !lines.is_empty() {
+                    self.comment(format!("TYPE:{}", feature));
+                }
+                for [start, end] in lines {
+                    self.travel_to(start)?;
+                    self.extrude_to(end, speed)?;
+                }
+            }
+            if !island.bridge_infill().is_empty() {
+                self.comment("TYPE:BRIDGE".to_string());
+                let fan_speed = self.fan_speed;
+                self.set_fan_speed(self.config.bridge_fan_speed);
+                for [start, end] in island.bridge_infill() {
+                    self.travel_to(start)?;
+                    self.extrude_to(end, self.feature_speed(self.config.bridge_speed))?;
+                }
+                self.set_fan_speed(fan_speed as f32 / 255.0);
+            }
+            if !island.ironing().is_empty() {
+                // ironing is marked as part of the top surface, like other slicers do
+                self.comment("TYPE:SKIN".to_string());
+                self.flow = self.config.ironing_flow as f64;
+                for [start, end] in island.ironing() {
+                    self.travel_to(start)?;
+                    self.extrude_to(end, speed)?;
+                }
+                self.flow = 1.0;
+            }
+            self.combing = None;
+        }
+
+        self.layer_index += 1;
+        Ok(())
+    }
 
-        self.top_height += (slice.thickness() * 200_000) as i64;
-        // increment z height
+    /// Sets the part cooling fan speed, from 0.0 (off) to 1.0 (full speed), if it isn't already at that speed
+    fn set_fan_speed(&mut self, speed: f32) {
+        let speed = (speed.clamp(0.0, 1.0) * 255.0).round() as u8;
+        if speed != self.fan_speed {
+            self.command(if speed == 0 { Command::FanOff } else { Command::SetFanSpeed(speed) });
+            self.fan_speed = speed;
+        }
+    }
+
+    /// The feedrate for extruding a feature that is normally printed at `speed`, which is overridden by
+    /// [ConfigProfile::first_layer_speed] on the first layer of the model
+    fn feature_speed(&self, speed: u32) -> u32 {
+        if self.layer_index == self.first_model_layer() { self.config.first_layer_speed } else { speed }
+    }
+
+    /// Index of the first layer of the model itself, which comes after the raft layers (if there are any)
+    fn first_model_layer(&self) -> usize {
+        self.config.raft_layers as usize
+    }
+
+    /// Moves the nozzle to the height of the current layer (plus the z-hop if it is lifted)
+    fn move_z(&mut self) -> Result<(), Error> {
+        let z_hop = match &self.config.retraction {
+            Some(retraction) if self.lifted => retraction.z_hop as i64,
+            _ => 0,
+        };
         self.command(Command::Move {
             speed: self.config.travel_speed,
             amounts: PerAxis::none()
-                .set(Axis::Z, self.top_height),
+                .set(Axis::Z, self.top_height.checked_add(z_hop).ok_or(Error::CoordinateOverflow)?),
         });
+        Ok(())
+    }
 
-        for island in slice.islands() {
-            //TODO: island holes
-            for vertex in island.outline().vertices() {
+    /// Retracts the filament and lifts the nozzle, if retraction is enabled. Does nothing if nothing has
+    /// been extruded yet or the filament is already retracted.
+    fn retract(&mut self) {
+        let retraction = match &self.config.retraction {
+            Some(retraction) if !self.retracted && self.extruder_position > 0 => retraction,
+            _ => return,
+        };
+        self.command(Command::SetRelativePositioning);
+        let wipe = self.wipe_path();
+        if wipe.is_empty() {
+            self.command(Command::ExtrudeMove {
+                speed: retraction.speed,
+                extrude_len: -(retraction.length as i64),
+                amounts: PerAxis::none(),
+            });
+        } else {
+            // spread the retraction over the wipe, in proportion to the length of each move
+            let wipe_length: f64 = std::iter::once(&self.position).chain(&wipe)
+                .zip(&wipe)
+                .map(|(from, to)| from.distance_to(to))
+                .sum();
+            let mut travelled = 0.0;
+            let mut retracted = 0;
+            for point in wipe {
+                travelled += self.position.distance_to(&point);
+                let total = (retraction.length as f64 * travelled / wipe_length).round() as i64;
                 self.command(Command::ExtrudeMove {
-                    speed: 1, //TODO
-                    extrude_len: 1, //TODO
+                    speed: self.config.travel_speed,
+                    extrude_len: -(total - retracted),
                     amounts: PerAxis::none()
-                        .set(Axis::X, vertex.x * 200_000)
-                        .set(Axis::Y, vertex.y * 200_000),
-                })
+                        .set(Axis::X, point.x - self.position.x)
+                        .set(Axis::Y, point.y - self.position.y),
+                });
+                retracted = total;
+                self.position = point;
             }
         }
+        self.printed_path = vec![self.position.clone()];
+        // otherwise the nozzle is lifted by travel_to, once it knows where it is going
+        if retraction.z_hop > 0 && !self.config.z_hop_only_when_crossing {
+            self.command(Command::Move {
+                speed: self.config.travel_speed,
+                amounts: PerAxis::none()
+                    .set(Axis::Z, retraction.z_hop as i64),
+            });
+            self.lifted = true;
+        }
+        self.command(Command::SetAbsolutePositioning);
+        self.retracted = true;
     }
 
-    fn generate_gcode(&self) -> String {
-        let mut s = String::new();
-        for cmd in self.commands.iter().map(Command::as_code) {
-            s.push_str(&cmd);
-            s.push('\n');
+    /// Lifts the nozzle by the z-hop before a travel move from the current position straight to `to`, if the
+    /// filament is retracted and the move crosses one of [GCodeBuilder::layer_outlines]. Returns an error if
+    /// the lifted height is too big to represent.
+    fn lift_for_crossing(&mut self, to: &Vector2D) -> Result<(), Error> {
+        let z_hop = match &self.config.retraction {
+            Some(retraction) if self.retracted && !self.lifted && retraction.z_hop > 0 => retraction.z_hop as i64,
+            _ => return Ok(()),
+        };
+        let from = &self.position;
+        let crosses = self.layer_outlines.iter()
+            .flat_map(|polygon| polygon.vertices().windows(2))
+            .any(|edge| geometry::segment_intersection(from, to, &edge[0], &edge[1]).is_some());
+        if crosses {
+            self.command(Command::Move {
+                speed: self.config.travel_speed,
+                amounts: PerAxis::none()
+                    .set(Axis::Z, self.top_height.checked_add(z_hop).ok_or(Error::CoordinateOverflow)?),
+            });
+            self.lifted = true;
+        }
+        Ok(())
+    }
+
+    /// The points to move back through to wipe the nozzle along the end of the printed path, going
+    /// [ConfigProfile::wipe_distance] back from the current position (or less if the path is shorter)
+    fn wipe_path(&self) -> Vec<Vector2D> {
+        let mut wipe = Vec::new();
+        let mut remaining = self.config.wipe_distance as f64;
+        let mut from = &self.position;
+        for point in self.printed_path.iter().rev().skip(1) {
+            if remaining <= 0.0 {
+                break;
+            }
+            let distance = from.distance_to(point);
+            if distance > remaining {
+                // stop partway along this segment
+                let fraction = remaining / distance;
+                wipe.push(Vector2D::new(
+                    from.x + ((point.x - from.x) as f64 * fraction).round() as i64,
+                    from.y + ((point.y - from.y) as f64 * fraction).round() as i64,
+                ));
+                break;
+            }
+            wipe.push(point.clone());
+            remaining -= distance;
+            from = point;
+        }
+        wipe
+    }
+
+    /// Records that the toolhead printed (or coasted) along a straight line to `to`
+    fn add_to_printed_path(&mut self, to: &Vector2D) {
+        if self.printed_path.is_empty() {
+            self.printed_path.push(self.position.clone());
+        }
+        self.printed_path.push(to.clone());
+        // the oldest points aren't needed once the rest of the path is long enough to wipe along
+        let wipe_distance = self.config.wipe_distance as f64;
+        while self.printed_path.len() > 2
+            && self.printed_path[1..].windows(2).map(|edge| edge[0].distance_to(&edge[1])).sum::<f64>() >= wipe_distance
+        {
+            self.printed_path.remove(0);
         }
+    }
+
+    /// Undoes [GCodeBuilder::retract], if the filament is retracted
+    fn unretract(&mut self) {
+        let retraction = match &self.config.retraction {
+            Some(retraction) if self.retracted => retraction,
+            _ => return,
+        };
+        self.command(Command::SetRelativePositioning);
+        if self.lifted {
+            self.command(Command::Move {
+                speed: self.config.travel_speed,
+                amounts: PerAxis::none()
+                    .set(Axis::Z, -(retraction.z_hop as i64)),
+            });
+            self.lifted = false;
+        }
+        self.command(Command::ExtrudeMove {
+            speed: retraction.speed,
+            extrude_len: retraction.length as i64,
+            amounts: PerAxis::none(),
+        });
+        self.command(Command::SetAbsolutePositioning);
+        self.retracted = false;
+    }
+
+    /// How far the toolhead has to travel to start printing `island`
+    fn entry_distance(&self, island: &SliceIsland) -> f64 {
+        let first_loop = island.brim().first()
+            .or_else(|| island.perimeters().first().and_then(|loops| loops.first()));
+        if let Some(polygon) = first_loop {
+            let nearest = nearest_vertex(polygon, &self.position);
+            self.position.distance_to(&polygon.vertices()[nearest])
+        } else if let Some([start, _]) = island.infill().first()
+            .or(island.solid_infill().first())
+            .or(island.bridge_infill().first())
+        {
+            self.position.distance_to(start)
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    /// Travels to the vertex of `polygon` that is closest to the toolhead, then extrudes all the way around
+    /// `polygon` back to that vertex, at `speed`. The last [ConfigProfile::coasting_distance] of the loop
+    /// is moved along without extruding.
+    fn add_loop(&mut self, polygon: &Polygon, speed: u32) -> Result<(), Error> {
+        self.add_loop_from(polygon, nearest_vertex(polygon, &self.position), speed)
+    }
+
+    /// The index of the vertex of `polygon` to start printing it at, according to [ConfigProfile::seam_position]
+    fn seam_vertex(&mut self, polygon: &Polygon) -> usize {
+        match self.config.seam_position {
+            SeamPosition::Nearest => nearest_vertex(polygon, &self.position),
+            SeamPosition::Aligned(degrees) => furthest_vertex(polygon, degrees),
+            SeamPosition::Random => {
+                // xorshift
+                self.seam_random_state ^= self.seam_random_state << 13;
+                self.seam_random_state ^= self.seam_random_state >> 7;
+                self.seam_random_state ^= self.seam_random_state << 17;
+                // the closing vertex is a duplicate of the first
+                (self.seam_random_state % (polygon.vertices().len() as u64 - 1)) as usize
+            },
+        }
+    }
+
+    /// Like [GCodeBuilder::add_loop], but starts at `vertices()[start]` of `polygon`
+    fn add_loop_from(&mut self, polygon: &Polygon, start: usize, speed: u32) -> Result<(), Error> {
+        let polygon = polygon.starting_at(start);
+        let start = &polygon.vertices()[0];
+        let length: f64 = polygon.vertices().windows(2).map(|edge| edge[0].distance_to(&edge[1])).sum();
+        let coasting_distance = self.config.coasting_distance as f64;
+        // how far around the loop to start coasting
+        let coast_from = if coasting_distance > 0.0 && length > coasting_distance {
+            length - coasting_distance
+        } else {
+            f64::INFINITY
+        };
+        self.travel_to(start)?;
+        let mut travelled = 0.0;
+        for (vertex, edge_length) in merge_short_edges(polygon.vertices(), self.config.min_segment_length as f64) {
+            if travelled + edge_length <= coast_from {
+                self.extrude_along(vertex, None, edge_length, speed)?;
+            } else {
+                if travelled < coast_from {
+                    // extrude up to where coasting starts partway along this edge
+                    let fraction = (coast_from - travelled) / edge_length;
+                    let split = Vector2D::new(
+                        self.position.x + ((vertex.x - self.position.x) as f64 * fraction).round() as i64,
+                        self.position.y + ((vertex.y - self.position.y) as f64 * fraction).round() as i64,
+                    );
+                    self.extrude_along(&split, None, coast_from - travelled, speed)?;
+                }
+                self.command(Command::Move {
+                    speed,
+                    amounts: PerAxis::none()
+                        .set(Axis::X, vertex.x)
+                        .set(Axis::Y, vertex.y),
+                });
+                self.add_to_printed_path(vertex);
+                self.position = vertex.clone();
+            }
+            travelled += edge_length;
+        }
+        Ok(())
+    }
+
+    /// Like [GCodeBuilder::add_loop], but the nozzle rises steadily from `bottom` on the way around, reaching
+    /// the top of the current layer back at the starting vertex
+    fn add_spiral_loop(&mut self, polygon: &Polygon, bottom: i64, speed: u32) -> Result<(), Error> {
+        let polygon = polygon.starting_at(nearest_vertex(polygon, &self.position));
+        let start = &polygon.vertices()[0];
+        let length: f64 = polygon.vertices().windows(2).map(|edge| edge[0].distance_to(&edge[1])).sum();
+        let rise = (self.top_height - bottom) as f64;
+        self.travel_to(start)?;
+        let mut travelled = 0.0;
+        for (vertex, edge_length) in merge_short_edges(polygon.vertices(), self.config.min_segment_length as f64) {
+            travelled += edge_length;
+            let fraction = if length > 0.0 { travelled / length } else { 1.0 };
+            self.extrude_along(vertex, Some(bottom + (rise * fraction).round() as i64), edge_length, speed)?;
+        }
+        Ok(())
+    }
+
+    /// Moves to `to` without extruding. While combing, the move goes around the inside of the current
+    /// island, retracting first if it has to cross open space. Returns an error if the nozzle is lifted
+    /// (see [GCodeBuilder::lift_for_crossing]) too high to represent.
+    fn travel_to(&mut self, to: &Vector2D) -> Result<(), Error> {
+        let path = self.combing.as_ref().and_then(|combing| combing.route(&self.position, to));
+        // moves to or from outside of the island (like from the brim) aren't combed
+        let crosses_island = path.is_none() && self.combing.as_ref()
+            .is_some_and(|combing| combing.contains(&self.position) && combing.contains(to));
+        if crosses_island {
+            self.retract();
+        }
+        // combed paths stay inside the island, away from its walls
+        if path.is_none() && self.config.z_hop_only_when_crossing {
+            self.lift_for_crossing(to)?;
+        }
+        for point in path.unwrap_or_else(|| vec![to.clone()]) {
+            self.command(Command::Move {
+                speed: self.config.travel_speed,
+                amounts: PerAxis::none()
+                    .set(Axis::X, point.x)
+                    .set(Axis::Y, point.y),
+            });
+        }
+        self.position = to.clone();
+        self.printed_path = vec![to.clone()];
+        Ok(())
+    }
+
+    /// Extrudes in a straight line to `to`, at `speed`
+    fn extrude_to(&mut self, to: &Vector2D, speed: u32) -> Result<(), Error> {
+        self.extrude_to_height(to, None, speed)
+    }
+
+    /// Extrudes in a straight line to `to`, also moving the nozzle to height `z` if it is given
+    fn extrude_to_height(&mut self, to: &Vector2D, z: Option<i64>, speed: u32) -> Result<(), Error> {
+        self.extrude_along(to, z, self.position.distance_to(to), speed)
+    }
+
+    /// Like [GCodeBuilder::extrude_to_height], but extrudes as much filament as a line `path_length` long
+    /// needs, for a move that stands in for a longer path
+    fn extrude_along(&mut self, to: &Vector2D, z: Option<i64>, path_length: f64, speed: u32) -> Result<(), Error> {
+        self.unretract();
+        let filament_length = self.filament_length(path_length);
+        self.extruder_position = self.extruder_position
+            .checked_add(filament_length)
+            .ok_or(Error::CoordinateOverflow)?;
+        let mut amounts = PerAxis::none()
+            .set(Axis::X, to.x)
+            .set(Axis::Y, to.y);
+        if let Some(z) = z {
+            amounts = amounts.set(Axis::Z, z);
+        }
+        self.command(Command::ExtrudeMove {
+            speed,
+            extrude_len: if self.config.relative_extrusion { filament_length } else { self.extruder_position },
+            amounts,
+        });
+        self.add_to_printed_path(to);
+        self.position = to.clone();
+        Ok(())
+    }
+
+    /// Length of filament needed to extrude a line that is `distance` long, one extrusion width wide,
+    /// and one layer thick. The volume of the line is the same as the volume of filament pushed in
+    /// (times `flow`).
+    fn filament_length(&self, distance: f64) -> i64 {
+        let line_area = self.config.extrusion_width() as f64 * self.layer_thickness as f64;
+        let filament_radius = self.config.filament_diameter as f64 / 2.0;
+        let filament_area = std::f64::consts::PI * filament_radius * filament_radius;
+        (line_area * distance * self.flow / filament_area).round() as i64
+    }
+
+    /// Total length of filament extruded by the commands (in millimeters). Retractions don't count, since
+    /// each one is undone before extruding again.
+    fn filament_used_mm(&self) -> f64 {
+        nanos_to_mm(self.extruder_position)
+    }
+
+    /// Total volume of filament extruded by the commands (in cubic millimeters)
+    fn filament_used_mm3(&self) -> f64 {
+        let radius = nanos_to_mm(self.config.filament_diameter as i64) / 2.0;
+        std::f64::consts::PI * radius * radius * self.filament_used_mm()
+    }
+
+    /// Estimates how long the printer will take to run the commands (see [GCodeBuilder::elapsed_seconds])
+    fn estimated_seconds(&self) -> f64 {
+        self.elapsed_seconds().last().copied().unwrap_or(0.0)
+    }
+
+    /// Estimates how long after the start of the print each command finishes, by adding up the length of
+    /// each move divided by its feedrate. Acceleration is ignored. Homing and waiting for temperatures are
+    /// counted as fixed amounts of time.
+    fn elapsed_seconds(&self) -> Vec<f64> {
+        let mut elapsed = Vec::with_capacity(self.commands.len());
+        let mut seconds = 0.0;
+        let mut relative = false;
+        let mut relative_extrusion = false;
+        // X, Y, Z, and E positions, in nanometers
+        let mut position = [0.0f64; 4];
+        let axis_index = |axis: &Axis| match axis {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+        };
+        // moves the simulated position, returning the distance travelled (in nanometers)
+        let move_to = |position: &mut [f64; 4], amounts: &PerAxis<i64>, extrude_len: Option<i64>, relative: bool, relative_extrusion: bool| {
+            let mut target = *position;
+            for (axis, &amount) in amounts.entries() {
+                let index = axis_index(axis);
+                target[index] = if relative { target[index] + amount as f64 } else { amount as f64 };
+            }
+            if let Some(len) = extrude_len {
+                target[3] = if relative || relative_extrusion { target[3] + len as f64 } else { len as f64 };
+            }
+            let deltas: Vec<f64> = position.iter().zip(target).map(|(from, to)| to - from).collect();
+            *position = target;
+            let distance = deltas[0].hypot(deltas[1]).hypot(deltas[2]);
+            // moves that only use the extruder take as long as the extruder takes
+            if distance == 0.0 { deltas[3].abs() } else { distance }
+        };
+        // time to cover `distance` nanometers at `speed` mm/min
+        let duration = |distance: f64, speed: u32| {
+            if speed == 0 { 0.0 } else { nanos_to_mm(distance as i64) / (speed as f64 / 60.0) }
+        };
+
+        for command in &self.commands {
+            use Command::*;
+            match command {
+                Home(axes) => {
+                    seconds += HOME_SECONDS;
+                    if axes.entries().next().is_none() {
+                        position[..3].fill(0.0);
+                    }
+                    for (axis, _) in axes.entries() {
+                        position[axis_index(axis)] = 0.0;
+                    }
+                },
+                SetAbsolutePositioning => relative = false,
+                SetRelativePositioning => relative = true,
+                Move { amounts, speed } => {
+                    let distance = move_to(&mut position, amounts, None, relative, relative_extrusion);
+                    seconds += duration(distance, *speed);
+                },
+                ExtrudeMove { amounts, speed, extrude_len } => {
+                    let distance = move_to(&mut position, amounts, Some(*extrude_len), relative, relative_extrusion);
+                    seconds += duration(distance, *speed);
+                },
+                ArcMove { end, center_offset: (i, j), clockwise, speed, extrude_len } => {
+                    let (start_x, start_y) = (position[0], position[1]);
+                    let chord = move_to(&mut position, end, *extrude_len, relative, relative_extrusion);
+                    let center = (start_x + *i as f64, start_y + *j as f64);
+                    let radius = (*i as f64).hypot(*j as f64);
+                    let start_angle = (start_y - center.1).atan2(start_x - center.0);
+                    let end_angle = (position[1] - center.1).atan2(position[0] - center.0);
+                    let mut sweep = if *clockwise { start_angle - end_angle } else { end_angle - start_angle };
+                    if sweep <= 0.0 {
+                        sweep += std::f64::consts::TAU;
+                    }
+                    let length = if radius == 0.0 { chord } else { radius * sweep };
+                    seconds += duration(length, *speed);
+                },
+                SetPosition(positions) => {
+                    for (axis, &pos) in positions.entries() {
+                        position[axis_index(axis)] = pos as f64;
+                    }
+                },
+                SetExtruderPosition(pos) => position[3] = *pos as f64,
+                SetAbsoluteExtrusion => relative_extrusion = false,
+                SetRelativeExtrusion => relative_extrusion = true,
+                BlockingSetTemp(_) | BlockingSetBedTemp(_) => seconds += HEAT_WAIT_SECONDS,
+                SetTemp(_) | SetBedTemp(_) | SetFanSpeed(_) | FanOff | SetAcceleration { .. } | SetJerk(_) | SetLinearAdvance(_) | SetProgress { .. } | FilamentChange | SelectTool(_) | Raw(_) | Comment(_) => {},
+            }
+            elapsed.push(seconds);
+        }
+
+        elapsed
+    }
+
+    /// Fills in the remaining time of each [Command::SetProgress], based on [GCodeBuilder::elapsed_seconds]
+    fn fill_in_remaining_time(&mut self) {
+        let elapsed = self.elapsed_seconds();
+        let total = elapsed.last().copied().unwrap_or(0.0);
+        for (command, seconds) in self.commands.iter_mut().zip(elapsed) {
+            if let Command::SetProgress { remaining_min, .. } = command {
+                *remaining_min = ((total - seconds) / 60.0).round().min(u16::MAX as f64) as u16;
+            }
+        }
+    }
+
+    fn generate_gcode(&self) -> String {
+        let threads = if self.commands.len() >= PARALLEL_RENDER_THRESHOLD {
+            std::thread::available_parallelism().map_or(1, |threads| threads.get())
+        } else {
+            1
+        };
+        self.render(threads)
+    }
+
+    /// Writes all the commands as G-code, split between `threads` threads. The commands themselves are
+    /// generated one layer after another, since each layer starts from the printer state (position, extruder
+    /// position, retraction, fan speed) left by the one before, but once they exist each is written
+    /// independently of the others, so chunks of them can be written on separate threads and joined up in order.
+    fn render(&self, threads: usize) -> String {
+        let (flavor, precision) = (self.config.firmware_flavor, self.config.precision);
+        let render = |commands: &[Command]| {
+            let mut s = String::new();
+            for cmd in commands.iter().map(|cmd| cmd.as_code_with_precision(flavor, precision)) {
+                s.push_str(&cmd);
+                s.push('\n');
+            }
+            s
+        };
+        let mut s = if threads > 1 && !self.commands.is_empty() {
+            let chunk_size = self.commands.len().div_ceil(threads);
+            std::thread::scope(|scope| {
+                let chunks: Vec<_> = self.commands.chunks(chunk_size)
+                    .map(|chunk| scope.spawn(move || render(chunk)))
+                    .collect();
+                chunks.into_iter().map(|chunk| chunk.join().unwrap()).collect::<String>()
+            })
+        } else {
+            render(&self.commands)
+        };
         // remove trailing newline
         s.pop();
         s
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Vector3D;
+    use crate::mesh::{Mesh, Scene};
+    use crate::slice::Slicer;
+    use crate::RetractionConfig;
+
+    /// A box with its corners at `min` and `max`
+    fn cuboid(min: Vector3D, max: Vector3D) -> Mesh {
+        // bit 0 of each index picks the x coordinate, bit 1 y, and bit 2 z
+        let vertices: Vec<Vector3D> = (0..8)
+            .map(|corner| Vector3D::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            ))
+            .collect();
+        let facets = [
+            [0, 2, 3], [0, 3, 1], [4, 5, 7], [4, 7, 6],
+            [0, 1, 5], [0, 5, 4], [2, 6, 7], [2, 7, 3],
+            [0, 4, 6], [0, 6, 2], [1, 3, 7], [1, 7, 5],
+        ];
+        Mesh::from_indexed(&vertices, &facets).unwrap()
+    }
+
+    fn slice_cuboid(config: &ConfigProfile, max: Vector3D) -> Result<Vec<Slice>, Error> {
+        let mut scene = Scene::new();
+        scene.add_mesh(cuboid(Vector3D::new(0, 0, 0), max));
+        Slicer::new(config).slice(scene)
+    }
+
+    #[test]
+    fn layer_moves_up_by_its_thickness() {
+        let config = ConfigProfile::builder().layer_height(200_000).build().unwrap();
+        let slices = slice_cuboid(&config, Vector3D::new(10_000_000, 10_000_000, 200_000)).unwrap();
+        assert_eq!(slices.len(), 1);
+        let gcode = slices_to_gcode(&config, &slices).unwrap();
+        assert!(gcode.lines().any(|line| line.starts_with("G1 Z0.2 ")), "no move to Z0.2 in:\n{}", gcode);
+    }
+
+    #[test]
+    fn first_layer_settings_skip_the_raft() {
+        let config = ConfigProfile::builder()
+            .raft_layers(2)
+            .skirt_loops(0)
+            .first_layer_speed(1234)
+            .fan_first_layer_off(true)
+            .comments(true)
+            .build()
+            .unwrap();
+        let slices = slice_cuboid(&config, Vector3D::new(10_000_000, 10_000_000, 2_000_000)).unwrap();
+        let gcode = slices_to_gcode(&config, &slices).unwrap();
+        // the G-code of each layer, starting with the first raft layer
+        let layers: Vec<&str> = gcode.split("; LAYER:").skip(1).collect();
+        let slow = |layer: &str| layer.lines().any(|line| line.starts_with("G1 ") && line.contains(" E") && line.contains("F1234"));
+        let fan_on = |layer: &str| layer.lines().any(|line| line.starts_with("M106"));
+        assert_eq!(layers.iter().map(|layer| slow(layer)).collect::<Vec<_>>()[..4], [false, false, true, false]);
+        assert_eq!(layers.iter().map(|layer| fan_on(layer)).collect::<Vec<_>>()[..4], [false, false, false, true]);
+    }
+
+    #[test]
+    fn writing_on_threads_matches_writing_sequentially() {
+        let config = ConfigProfile::builder().comments(true).build().unwrap();
+        let slices = slice_cuboid(&config, Vector3D::new(20_000_000, 10_000_000, 3_000_000)).unwrap();
+        let mut gcoder = GCodeBuilder::new(&config, slices.len());
+        gcoder.add_starting_gcode();
+        for slice in &slices {
+            gcoder.add_slice(slice).unwrap();
+        }
+        gcoder.add_ending_gcode();
+        let sequential = gcoder.render(1);
+        for threads in [2, 3, 8, gcoder.commands.len() + 1] {
+            assert!(gcoder.render(threads) == sequential, "output differs on {} threads", threads);
+        }
+    }
+
+    #[test]
+    fn heights_past_i64_max_are_an_error() {
+        let layer_height = 1 << 61;
+        let config = ConfigProfile::builder().layer_height(layer_height).build().unwrap();
+        // the top of the fourth layer is past i64::MAX
+        let result = slice_cuboid(&config, Vector3D::new(10_000_000, 10_000_000, i64::MAX - 1));
+        assert!(matches!(result, Err(Error::CoordinateOverflow)));
+
+        // three layers fit, but not once the nozzle is lifted above the last one
+        let retraction = RetractionConfig { length: 1_000_000, speed: 2400, z_hop: 1 << 62 };
+        let config = ConfigProfile::builder().layer_height(layer_height).retraction(Some(retraction)).build().unwrap();
+        let slices = slice_cuboid(&config, Vector3D::new(10_000_000, 10_000_000, 13 * (layer_height as i64 / 4))).unwrap();
+        assert_eq!(slices.len(), 3);
+        assert!(matches!(slices_to_gcode(&config, &slices), Err(Error::CoordinateOverflow)));
+    }
+
+    #[test]
+    fn parse_reverses_as_code() {
+        let commands = vec![
+            Command::Home(PerAxis::none()),
+            Command::SetAbsolutePositioning,
+            Command::Move { amounts: PerAxis::none().set(Axis::X, 12_345_000).set(Axis::Y, -3_000_000), speed: 6000 },
+            Command::Move { amounts: PerAxis::none().set(Axis::Z, 200_000), speed: 6000 },
+            Command::ExtrudeMove { amounts: PerAxis::none().set(Axis::X, 20_000_000).set(Axis::Y, 1_500_000), speed: 1200, extrude_len: 1_234_560 },
+            Command::ArcMove {
+                end: PerAxis::none().set(Axis::X, 10_000_000).set(Axis::Y, 5_000_000),
+                center_offset: (-2_500_000, 0),
+                clockwise: true,
+                speed: 1800,
+                extrude_len: Some(2_000_000),
+            },
+            Command::ArcMove {
+                end: PerAxis::none().set(Axis::X, 0).set(Axis::Y, 0),
+                center_offset: (1_000_000, 1_000_000),
+                clockwise: false,
+                speed: 1800,
+                extrude_len: None,
+            },
+            Command::SetTemp(210),
+            Command::BlockingSetTemp(215),
+            Command::SetBedTemp(60),
+            Command::BlockingSetBedTemp(65),
+            Command::SetFanSpeed(255),
+            Command::SetFanSpeed(128),
+            Command::Raw("M117 Printing".to_string()),
+        ];
+        for flavor in [FirmwareFlavor::Marlin, FirmwareFlavor::RepRapFirmware, FirmwareFlavor::Klipper] {
+            let text = commands.iter().map(|command| command.as_code(flavor)).collect::<Vec<_>>().join("\n");
+            assert_eq!(parse(&text).unwrap(), commands, "{:?}", flavor);
+        }
+        // RepRapFirmware turns the fan off with M106 S0, which reads back as SetFanSpeed(0)
+        for flavor in [FirmwareFlavor::Marlin, FirmwareFlavor::Klipper] {
+            assert_eq!(parse(&Command::FanOff.as_code(flavor)).unwrap(), vec![Command::FanOff]);
+        }
+        assert_eq!(parse(&Command::FanOff.as_code(FirmwareFlavor::RepRapFirmware)).unwrap(), vec![Command::SetFanSpeed(0)]);
+    }
+}
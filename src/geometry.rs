@@ -1,4 +1,30 @@
-#[derive(Debug, Clone)]
+use crate::Error;
+
+pub mod boolean;
+
+/// Number of nanometers in a millimeter. Every length in the library (coordinates, layer heights, extrusion
+/// widths, ...) is a whole number of nanometers; millimeters are only used at the edges, in mesh files, G-code,
+/// and serialized profiles.
+pub const NANOS_PER_MILLIMETER: f64 = 1_000_000.0;
+
+/// Converts millimeters to nanometers, rounding to the nearest nanometer. Values too big for an `i64` saturate.
+fn mm_to_nanos(mm: f64) -> i64 {
+    (mm * NANOS_PER_MILLIMETER).round() as i64
+}
+
+fn nanos_to_mm(nanos: i64) -> f64 {
+    nanos as f64 / NANOS_PER_MILLIMETER
+}
+
+/// One of the three coordinate axes. The variant order is the order that axes are written in G-code.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Vector3D {
     pub x: i64,
     pub y: i64,
@@ -14,6 +40,20 @@ impl Vector3D {
         }
     }
 
+    /// The vector with coordinates given in millimeters, rounded to the nearest nanometer
+    pub fn from_mm(x: f64, y: f64, z: f64) -> Self {
+        Self::new(mm_to_nanos(x), mm_to_nanos(y), mm_to_nanos(z))
+    }
+
+    pub fn to_array(&self) -> [i64; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    /// The coordinates of the vector in millimeters
+    pub fn to_mm(&self) -> [f64; 3] {
+        [nanos_to_mm(self.x), nanos_to_mm(self.y), nanos_to_mm(self.z)]
+    }
+
     /// Adds `other` to this vector.
     pub fn add(&mut self, other: &Self) {
         self.x += other.x;
@@ -21,6 +61,66 @@ impl Vector3D {
         self.z += other.z;
     }
 
+    /// Returns the vector from `other` to this vector (`self - other`).
+    pub fn sub(&self, other: &Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+
+    /// Returns the dot product `self . other`.
+    ///
+    /// Each product of two nanometer coordinates can exceed `i64`, so the result is an `i128`. The sum of
+    /// the three products can still overflow that when components are near `i64::MAX`, but it can't as
+    /// long as every component's magnitude is below 2^62 (about 4.6 million kilometers).
+    pub fn dot(&self, other: &Self) -> i128 {
+        self.x as i128 * other.x as i128
+            + self.y as i128 * other.y as i128
+            + self.z as i128 * other.z as i128
+    }
+
+    /// Returns the cross product `self x other`.
+    ///
+    /// Unlike [Vector3D::dot], the result is kept as `i64`s, so the components (which are products of two
+    /// coordinates) overflow if the vectors are longer than ~2*10^9 (2 meters, in nanometers). That is
+    /// plenty for differences between points on a printable mesh, but not for arbitrary coordinates.
+    pub fn cross(&self, other: &Self) -> Self {
+        Self::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    /// Like [Vector3D::cross], but with `i128` components, so that it is exact as long as every component's
+    /// magnitude is below 2^62 (the same limit as [Vector3D::dot]).
+    pub(crate) fn wide_cross(&self, other: &Self) -> [i128; 3] {
+        let (a, b) = ([self.x, self.y, self.z].map(i128::from), [other.x, other.y, other.z].map(i128::from));
+        [
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ]
+    }
+
+    /// Rotates this vector `radians` counterclockwise (looking down the axis at the origin) about `axis`.
+    /// The result is rounded to the nearest integer coordinates.
+    pub(crate) fn rotated(&self, axis: Axis, radians: f64) -> Self {
+        let (sin, cos) = radians.sin_cos();
+        // the two coordinates that change, in right-handed order
+        let (a, b) = match axis {
+            Axis::X => (self.y, self.z),
+            Axis::Y => (self.z, self.x),
+            Axis::Z => (self.x, self.y),
+        };
+        let (a, b) = (a as f64, b as f64);
+        let new_a = (a * cos - b * sin).round() as i64;
+        let new_b = (a * sin + b * cos).round() as i64;
+        match axis {
+            Axis::X => Self::new(self.x, new_a, new_b),
+            Axis::Y => Self::new(new_b, self.y, new_a),
+            Axis::Z => Self::new(new_a, new_b, self.z),
+        }
+    }
+
     /// Creates a 2D vector of this 3D vector without the z
     pub(crate) fn to_2d_at_z(&self) -> Vector2D {
         Vector2D::new(self.x, self.y)
@@ -41,7 +141,59 @@ impl Vector3D {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl From<[i64; 3]> for Vector3D {
+    fn from([x, y, z]: [i64; 3]) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
+impl From<(i64, i64, i64)> for Vector3D {
+    fn from((x, y, z): (i64, i64, i64)) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
+impl From<Vector3D> for [i64; 3] {
+    fn from(vector: Vector3D) -> Self {
+        vector.to_array()
+    }
+}
+
+/// An axis-aligned box, given by its corners with the lowest and highest coordinates
+#[derive(Debug, Clone)]
+pub struct BoundingBox3D {
+    pub min: Vector3D,
+    pub max: Vector3D,
+}
+
+impl BoundingBox3D {
+    /// The smallest box containing all of `points`, or `None` if there are no points
+    pub fn from_points<'a>(points: impl IntoIterator<Item=&'a Vector3D>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        let mut bounds = Self {
+            min: first.clone(),
+            max: first.clone(),
+        };
+        for point in points {
+            bounds.min.x = bounds.min.x.min(point.x);
+            bounds.min.y = bounds.min.y.min(point.y);
+            bounds.min.z = bounds.min.z.min(point.z);
+            bounds.max.x = bounds.max.x.max(point.x);
+            bounds.max.y = bounds.max.y.max(point.y);
+            bounds.max.z = bounds.max.z.max(point.z);
+        }
+        Some(bounds)
+    }
+
+    /// The size of the box along each axis
+    pub fn size(&self) -> Vector3D {
+        self.max.sub(&self.min)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Vector2D {
     pub x: i64,
     pub y: i64,
@@ -54,10 +206,75 @@ impl Vector2D {
             y,
         }
     }
+
+    /// The vector with coordinates given in millimeters, rounded to the nearest nanometer
+    pub fn from_mm(x: f64, y: f64) -> Self {
+        Self::new(mm_to_nanos(x), mm_to_nanos(y))
+    }
+
+    pub fn to_array(&self) -> [i64; 2] {
+        [self.x, self.y]
+    }
+
+    /// The coordinates of the vector in millimeters
+    pub fn to_mm(&self) -> [f64; 2] {
+        [nanos_to_mm(self.x), nanos_to_mm(self.y)]
+    }
+
+    /// Adds `other` to this vector.
+    pub fn add(&mut self, other: &Self) {
+        self.x += other.x;
+        self.y += other.y;
+    }
+
+    /// Returns the vector from `other` to this vector (`self - other`).
+    pub fn sub(&self, other: &Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y)
+    }
+
+    /// Returns the dot product `self . other`, exactly (see [Vector3D::dot]).
+    pub fn dot(&self, other: &Self) -> i128 {
+        self.x as i128 * other.x as i128 + self.y as i128 * other.y as i128
+    }
+
+    /// Returns the z component of the 3D cross product `self x other`, exactly. It is positive if `other`
+    /// is counterclockwise from `self`, negative if it is clockwise, and 0 if they are parallel.
+    pub fn cross(&self, other: &Self) -> i128 {
+        self.x as i128 * other.y as i128 - self.y as i128 * other.x as i128
+    }
+
+    /// The length of this vector
+    pub fn length(&self) -> f64 {
+        (self.x as f64).hypot(self.y as f64)
+    }
+
+    /// The straight-line distance between this point and `other`
+    pub fn distance_to(&self, other: &Self) -> f64 {
+        other.sub(self).length()
+    }
+}
+
+impl From<[i64; 2]> for Vector2D {
+    fn from([x, y]: [i64; 2]) -> Self {
+        Self::new(x, y)
+    }
+}
+
+impl From<(i64, i64)> for Vector2D {
+    fn from((x, y): (i64, i64)) -> Self {
+        Self::new(x, y)
+    }
+}
+
+impl From<Vector2D> for [i64; 2] {
+    fn from(vector: Vector2D) -> Self {
+        vector.to_array()
+    }
 }
 
 /// A closed 2D polygon
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Polygon {
     vertices: Vec<Vector2D>,
 }
@@ -72,6 +289,554 @@ impl Polygon {
     pub fn vertices(&self) -> &[Vector2D] {
         &self.vertices
     }
+
+    /// The area of the polygon, computed with the shoelace formula. Positive if the vertices go
+    /// counterclockwise, negative if they go clockwise.
+    pub fn signed_area(&self) -> f64 {
+        // the closing vertex is a repeat of the first, which adds a zero-length edge that doesn't change the sum
+        Self::doubled_signed_area(&self.vertices) as f64 / 2.0
+    }
+
+    /// Whether `point` is inside the polygon. Points exactly on an edge or vertex count as inside.
+    ///
+    /// Uses the even-odd rule, so for self-intersecting polygons, areas that are enclosed an even number of
+    /// times count as outside.
+    pub fn contains(&self, point: &Vector2D) -> bool {
+        let mut inside = false;
+        for edge in self.vertices.windows(2) {
+            let (a, b) = (&edge[0], &edge[1]);
+            let on_line = b.sub(a).cross(&point.sub(a)) == 0;
+            let within_bounds = point.x >= a.x.min(b.x) && point.x <= a.x.max(b.x)
+                && point.y >= a.y.min(b.y) && point.y <= a.y.max(b.y);
+            if on_line && within_bounds {
+                return true;
+            }
+            // count crossings of a ray going in the +x direction from `point`. The comparison is half-open
+            // so that a ray passing exactly through a vertex only counts one of the vertex's two edges.
+            if (a.y > point.y) != (b.y > point.y) {
+                let crossing_x = a.x as f64 + (point.y - a.y) as f64 * (b.x - a.x) as f64 / (b.y - a.y) as f64;
+                if crossing_x > point.x as f64 {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+
+    /// Splits the polygon into triangles (by ear clipping) that exactly cover it without overlapping. The
+    /// triangles go counterclockwise, whichever way the polygon goes. Vertices that are on a straight line
+    /// between their neighbours don't get triangles of their own.
+    ///
+    /// Returns [Error::SelfIntersectingPolygon] if any of the polygon's edges cross or touch each other,
+    /// because that would make some of the triangles overlap.
+    pub fn triangulate(&self) -> Result<Vec<[Vector2D; 3]>, Error> {
+        let mut vertices = self.open_vertices();
+        if Self::doubled_signed_area(&vertices) < 0 {
+            vertices.reverse();
+        }
+        let is_simple = (0..vertices.len()).all(|i| (i + 2..vertices.len()).all(|j| {
+            let n = vertices.len();
+            // the first and last edges are next to each other, so they touch at a vertex
+            if i == 0 && j == n - 1 {
+                return true;
+            }
+            let (a0, a1) = (&vertices[i], &vertices[(i + 1) % n]);
+            let (b0, b1) = (&vertices[j], &vertices[(j + 1) % n]);
+            segment_crossing(a0, a1, b0, b1).is_none()
+                && !on_segment(a0, b0, b1) && !on_segment(a1, b0, b1)
+                && !on_segment(b0, a0, a1) && !on_segment(b1, a0, a1)
+        }));
+        if !is_simple {
+            return Err(Error::SelfIntersectingPolygon);
+        }
+
+        let mut triangles = Vec::with_capacity(vertices.len().saturating_sub(2));
+        while vertices.len() >= 3 {
+            let n = vertices.len();
+            let corner = |i: usize| (&vertices[(i + n - 1) % n], &vertices[i], &vertices[(i + 1) % n]);
+            // a vertex that is on a line between its neighbours can be dropped without changing the shape
+            if let Some(i) = (0..n).find(|&i| {
+                let (a, b, c) = corner(i);
+                b.sub(a).cross(&c.sub(b)) == 0
+            }) {
+                vertices.remove(i);
+                continue;
+            }
+            // an ear is a corner that turns left and doesn't have any other vertex inside of it
+            let ear = (0..n).find(|&i| {
+                let (a, b, c) = corner(i);
+                b.sub(a).cross(&c.sub(b)) > 0 && !vertices.iter()
+                    .filter(|vertex| ![a, b, c].contains(vertex))
+                    .any(|vertex| is_in_triangle(vertex, a, b, c))
+            });
+            // every simple polygon has an ear
+            let i = ear.ok_or(Error::SelfIntersectingPolygon)?;
+            let (a, b, c) = corner(i);
+            triangles.push([a.clone(), b.clone(), c.clone()]);
+            vertices.remove(i);
+        }
+        Ok(triangles)
+    }
+
+    /// Whether the vertices of the polygon go clockwise
+    pub fn is_clockwise(&self) -> bool {
+        self.signed_area() < 0.0
+    }
+
+    /// Flips the winding direction of the polygon
+    pub fn reverse(&mut self) {
+        self.vertices.reverse();
+    }
+
+    /// Returns the same polygon, but with its vertices rotated so that it starts (and ends) at `vertices()[index]`
+    pub fn starting_at(&self, index: usize) -> Polygon {
+        let open = &self.vertices[..self.vertices.len() - 1];
+        let index = index % open.len();
+        let rotated: Vec<Vector2D> = open[index..].iter().chain(&open[..index]).cloned().collect();
+        Self::from_open_vertices(&rotated)
+    }
+
+    /// Returns a copy of the polygon with fewer vertices. Vertices exactly in line with their neighbours are
+    /// always removed, then the Douglas-Peucker algorithm removes vertices as long as the outline doesn't
+    /// move more than `tolerance` away from any of the original vertices.
+    ///
+    /// The result always keeps at least three distinct vertices (unless the polygon didn't have that many
+    /// to begin with, in which case it is returned unchanged).
+    pub fn simplify(&self, tolerance: i64) -> Polygon {
+        let mut vertices: Vec<Vector2D> = Vec::with_capacity(self.vertices.len());
+        for vertex in self.open_vertices() {
+            vertices.push(vertex);
+            // the new vertex can make the one before it collinear, and removing that one can make
+            // the one before it collinear too, and so on
+            while vertices.len() >= 3 && is_collinear(&vertices[vertices.len() - 3], &vertices[vertices.len() - 2], &vertices[vertices.len() - 1]) {
+                vertices.remove(vertices.len() - 2);
+            }
+        }
+        // the same, around the closing vertex
+        loop {
+            let n = vertices.len();
+            if n >= 3 && is_collinear(&vertices[n - 2], &vertices[n - 1], &vertices[0]) {
+                vertices.pop();
+            } else if n >= 3 && is_collinear(&vertices[n - 1], &vertices[0], &vertices[1]) {
+                vertices.remove(0);
+            } else {
+                break;
+            }
+        }
+        if vertices.len() < 3 {
+            return Self::from_open_vertices(&self.vertices[..self.vertices.len() - 1]);
+        }
+
+        // split the loop into two chains between the first vertex and the vertex furthest from it, which
+        // are both certainly kept
+        let far = (1..vertices.len())
+            .max_by(|&a, &b| vertices[0].distance_to(&vertices[a]).total_cmp(&vertices[0].distance_to(&vertices[b])))
+            .unwrap();
+        let closed: Vec<Vector2D> = vertices.iter().chain(std::iter::once(&vertices[0])).cloned().collect();
+        let mut keep = vec![false; closed.len()];
+        keep[0] = true;
+        keep[far] = true;
+        douglas_peucker(&closed, 0, far, tolerance as f64, &mut keep);
+        douglas_peucker(&closed, far, vertices.len(), tolerance as f64, &mut keep);
+        // the closing vertex is the same as the first one
+        keep.pop();
+
+        if keep.iter().filter(|&&kept| kept).count() < 3 {
+            // only the two chain ends were kept. Keep whichever vertex sticks out furthest from the line between them
+            let furthest = (1..vertices.len())
+                .filter(|&i| i != far)
+                .max_by(|&a, &b| {
+                    let distance = |i: usize| distance_to_segment(&vertices[i], &vertices[0], &vertices[far]);
+                    distance(a).total_cmp(&distance(b))
+                })
+                .unwrap();
+            keep[furthest] = true;
+        }
+        let kept: Vec<Vector2D> = vertices.into_iter().zip(keep).filter(|(_, kept)| *kept).map(|(vertex, _)| vertex).collect();
+        Self::from_open_vertices(&kept)
+    }
+
+    /// Builds a polygon from a list of vertices that doesn't include the closing vertex
+    fn from_open_vertices(vertices: &[Vector2D]) -> Self {
+        let mut builder = Polygon::builder(vertices[0].clone());
+        for vertex in &vertices[1..] {
+            builder.line_to(vertex.clone());
+        }
+        builder.close()
+    }
+
+    /// The vertices of this polygon without repeated consecutive vertices and without the closing vertex
+    fn open_vertices(&self) -> Vec<Vector2D> {
+        let mut vertices: Vec<Vector2D> = Vec::with_capacity(self.vertices.len());
+        for vertex in &self.vertices {
+            if vertices.last() != Some(vertex) {
+                vertices.push(vertex.clone());
+            }
+        }
+        while vertices.len() > 1 && vertices.first() == vertices.last() {
+            vertices.pop();
+        }
+        vertices
+    }
+
+    /// Twice the signed area of the polygon, positive if the vertices are counterclockwise
+    fn doubled_signed_area(vertices: &[Vector2D]) -> i128 {
+        let mut area = 0i128;
+        for (i, a) in vertices.iter().enumerate() {
+            let b = &vertices[(i + 1) % vertices.len()];
+            area += a.x as i128 * b.y as i128 - b.x as i128 * a.y as i128;
+        }
+        area
+    }
+
+    /// Offsets every edge of the polygon by `distance` along the edge's normal. Positive distances move
+    /// the edges towards the inside of the polygon (an inset), negative distances move them outwards.
+    ///
+    /// Edges that are too short to survive the offset are dropped and their neighbours are extended to
+    /// meet each other. Parts of the polygon that are thinner than twice an inset disappear, which can
+    /// split the polygon into several separate polygons. If the whole polygon collapses, an empty `Vec` is
+    /// returned. The resulting polygons never cross themselves, and have the same winding direction as
+    /// the original.
+    pub fn offset(&self, distance: i64) -> Vec<Polygon> {
+        /// Corners that would stick out further than this many times `distance` get beveled
+        const MITER_LIMIT: f64 = 2.0;
+
+        let vertices = self.open_vertices();
+        if vertices.len() < 3 {
+            return Vec::new();
+        }
+        let orientation = Self::doubled_signed_area(&vertices).signum() as f64;
+        if orientation == 0.0 {
+            return Vec::new();
+        }
+        if distance == 0 {
+            return vec![Self::from_open_vertices(&vertices)];
+        }
+        let distance = distance as f64;
+
+        // each edge's start point and unit direction, for an edge from vertices[i] to vertices[i + 1]
+        let edges: Vec<((f64, f64), (f64, f64))> = vertices.iter().enumerate()
+            .map(|(i, start)| {
+                let end = &vertices[(i + 1) % vertices.len()];
+                let (dx, dy) = ((end.x - start.x) as f64, (end.y - start.y) as f64);
+                let len = dx.hypot(dy);
+                ((start.x as f64, start.y as f64), (dx / len, dy / len))
+            })
+            .collect();
+        // normal of each edge pointing in the direction that the offset moves it
+        let normal = |edge: usize| {
+            let (_, (dx, dy)) = edges[edge];
+            (-dy * orientation, dx * orientation)
+        };
+        // where the offset edge `b` starts, i.e. where it intersects the offset of the preceding edge `a`
+        let corner = |a: usize, b: usize| {
+            let ((ax, ay), (adx, ady)) = edges[a];
+            let ((bx, by), (bdx, bdy)) = edges[b];
+            let (na, nb) = (normal(a), normal(b));
+            let (ax, ay) = (ax + na.0 * distance, ay + na.1 * distance);
+            let (bx, by) = (bx + nb.0 * distance, by + nb.1 * distance);
+            let denominator = adx * bdy - ady * bdx;
+            if denominator.abs() < 1e-9 {
+                // parallel edges, or edges doubling back on each other
+                (bx, by)
+            } else {
+                let t = ((bx - ax) * bdy - (by - ay) * bdx) / denominator;
+                (ax + adx * t, ay + ady * t)
+            }
+        };
+
+        // repeatedly drop edges whose direction got reversed by the offset, until none are left
+        let mut active: Vec<usize> = (0..edges.len()).collect();
+        loop {
+            if active.len() < 3 {
+                return Vec::new();
+            }
+            let starts: Vec<(f64, f64)> = (0..active.len())
+                .map(|i| corner(active[(i + active.len() - 1) % active.len()], active[i]))
+                .collect();
+            let survivors: Vec<usize> = (0..active.len())
+                .filter(|&i| {
+                    let (start, end) = (starts[i], starts[(i + 1) % starts.len()]);
+                    let (_, (dx, dy)) = edges[active[i]];
+                    (end.0 - start.0) * dx + (end.1 - start.1) * dy > 0.0
+                })
+                .map(|i| active[i])
+                .collect();
+            if survivors.len() == active.len() {
+                break;
+            }
+            active = survivors;
+        }
+
+        let mut offset_vertices = Vec::with_capacity(active.len());
+        for (i, &edge) in active.iter().enumerate() {
+            let previous = active[(i + active.len() - 1) % active.len()];
+            let (x, y) = corner(previous, edge);
+            let ((ox, oy), _) = edges[edge];
+            let (_, (pdx, pdy)) = edges[previous];
+            let (_, (dx, dy)) = edges[edge];
+            let turns_away = (pdx * dy - pdy * dx) * orientation * distance.signum() < 0.0;
+            if turns_away && (x - ox).hypot(y - oy) > MITER_LIMIT * distance.abs() {
+                // bevel the corner instead of extending it into a long spike
+                let (pn, n) = (normal(previous), normal(edge));
+                offset_vertices.push(Vector2D::new((ox + pn.0 * distance).round() as i64, (oy + pn.1 * distance).round() as i64));
+                offset_vertices.push(Vector2D::new((ox + n.0 * distance).round() as i64, (oy + n.1 * distance).round() as i64));
+            } else {
+                offset_vertices.push(Vector2D::new(x.round() as i64, y.round() as i64));
+            }
+        }
+        offset_vertices.dedup();
+        if offset_vertices.len() > 1 && offset_vertices.first() == offset_vertices.last() {
+            offset_vertices.pop();
+        }
+        if offset_vertices.len() < 3 {
+            return Vec::new();
+        }
+
+        // moving the edges can make the outline cross itself, e.g. where a thin neck of the polygon
+        // collapses. Split it at the crossings and keep only the loops that are really at `distance`
+        // from the original: the others are turned inside out, or too close to the original's edges.
+        split_self_intersections(&offset_vertices).into_iter()
+            .filter(|vertices| Self::doubled_signed_area(vertices).signum() as f64 == orientation)
+            .filter(|vertices| {
+                let (a, b) = longest_edge(vertices);
+                let midpoint = Vector2D::new((a.x + b.x) / 2, (a.y + b.y) / 2);
+                self.distance_to_edges(&midpoint) > 0.9 * distance.abs()
+            })
+            .map(|vertices| Self::from_open_vertices(&vertices))
+            .collect()
+    }
+
+    /// The distance from `point` to the closest point on any edge of the polygon
+    fn distance_to_edges(&self, point: &Vector2D) -> f64 {
+        self.vertices.windows(2)
+            .map(|edge| distance_to_segment(point, &edge[0], &edge[1]))
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+/// The smallest convex polygon containing all of `points`, going counterclockwise. Returns `None` if
+/// there are fewer than three distinct points, or they are all on one line, since then the hull has no area.
+pub fn convex_hull(points: &[Vector2D]) -> Option<Polygon> {
+    let mut points: Vec<&Vector2D> = points.iter().collect();
+    points.sort_unstable_by_key(|point| (point.x, point.y));
+    points.dedup();
+
+    // Andrew's monotone chain: build the lower half of the hull from left to right, then the upper half
+    // from right to left, dropping points that would make the hull turn clockwise
+    let turns_clockwise = |hull: &[&Vector2D], point: &Vector2D| {
+        let (a, b) = (hull[hull.len() - 2], hull[hull.len() - 1]);
+        b.sub(a).cross(&point.sub(b)) <= 0
+    };
+    let mut hull: Vec<&Vector2D> = Vec::with_capacity(points.len() + 1);
+    for &point in &points {
+        while hull.len() >= 2 && turns_clockwise(&hull, point) {
+            hull.pop();
+        }
+        hull.push(point);
+    }
+    let lower_len = hull.len();
+    for &point in points.iter().rev().skip(1) {
+        while hull.len() > lower_len && turns_clockwise(&hull, point) {
+            hull.pop();
+        }
+        hull.push(point);
+    }
+    // the last point is the first one again
+    hull.pop();
+
+    if hull.len() < 3 {
+        return None;
+    }
+    let hull: Vec<Vector2D> = hull.into_iter().cloned().collect();
+    Some(Polygon::from_open_vertices(&hull))
+}
+
+/// Whether `point` is inside the counterclockwise triangle a, b, c, or on its edges
+fn is_in_triangle(point: &Vector2D, a: &Vector2D, b: &Vector2D, c: &Vector2D) -> bool {
+    [(a, b), (b, c), (c, a)].iter().all(|(from, to)| to.sub(from).cross(&point.sub(from)) >= 0)
+}
+
+/// Whether the three points are on the same line
+fn is_collinear(a: &Vector2D, b: &Vector2D, c: &Vector2D) -> bool {
+    b.sub(a).cross(&c.sub(b)) == 0
+}
+
+/// Marks which of `vertices[start + 1..end]` to keep in `keep`, so that the line through the kept vertices
+/// stays within `tolerance` of all the removed ones (the Douglas-Peucker algorithm)
+fn douglas_peucker(vertices: &[Vector2D], start: usize, end: usize, tolerance: f64, keep: &mut [bool]) {
+    let furthest = (start + 1..end)
+        .map(|i| (i, distance_to_segment(&vertices[i], &vertices[start], &vertices[end])))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    if let Some((i, distance)) = furthest {
+        if distance > tolerance {
+            keep[i] = true;
+            douglas_peucker(vertices, start, i, tolerance, keep);
+            douglas_peucker(vertices, i, end, tolerance, keep);
+        }
+    }
+}
+
+/// The distance from `point` to the closest point on the line segment a---b
+fn distance_to_segment(point: &Vector2D, a: &Vector2D, b: &Vector2D) -> f64 {
+    let ab = b.sub(a);
+    let length_squared = ab.dot(&ab) as f64;
+    if length_squared == 0.0 {
+        return point.distance_to(a);
+    }
+    let t = (point.sub(a).dot(&ab) as f64 / length_squared).clamp(0.0, 1.0);
+    let closest_x = a.x as f64 + ab.x as f64 * t;
+    let closest_y = a.y as f64 + ab.y as f64 * t;
+    (point.x as f64 - closest_x).hypot(point.y as f64 - closest_y)
+}
+
+/// The two endpoints of the longest edge of the closed loop `vertices`
+fn longest_edge(vertices: &[Vector2D]) -> (&Vector2D, &Vector2D) {
+    (0..vertices.len())
+        .map(|i| (&vertices[i], &vertices[(i + 1) % vertices.len()]))
+        .max_by(|(a1, b1), (a2, b2)| a1.distance_to(b1).total_cmp(&a2.distance_to(b2)))
+        .unwrap()
+}
+
+/// Where the segments a0---a1 and b0---b1 meet, or `None` if they don't.
+///
+/// If the segments cross, the crossing point is rounded to the nearest integer coordinates (nanometers),
+/// so it may be very slightly off of both segments. If they only touch (one segment's endpoint is on the
+/// other segment, including when they share an endpoint), that endpoint is returned exactly. If they are
+/// collinear and overlap, the end of the overlap that is closest to `a0` is returned.
+pub fn segment_intersection(a0: &Vector2D, a1: &Vector2D, b0: &Vector2D, b1: &Vector2D) -> Option<Vector2D> {
+    if let Some(crossing) = segment_crossing(a0, a1, b0, b1) {
+        return Some(crossing);
+    }
+    // segments that meet without crossing meet at the endpoint of one of them
+    [a0, a1, b0, b1].into_iter()
+        .filter(|point| on_segment(point, a0, a1) && on_segment(point, b0, b1))
+        .min_by(|p, q| a0.distance_to(p).total_cmp(&a0.distance_to(q)))
+        .cloned()
+}
+
+/// If the segments a0---a1 and b0---b1 cross at a single point that isn't an endpoint of either segment,
+/// returns that point, rounded to the nearest integer coordinates.
+pub(crate) fn segment_crossing(a0: &Vector2D, a1: &Vector2D, b0: &Vector2D, b1: &Vector2D) -> Option<Vector2D> {
+    let (a, b) = (a1.sub(a0), b1.sub(b0));
+    // which side of each segment the other segment's endpoints are on
+    let b0_side = a.cross(&b0.sub(a0)).signum();
+    let b1_side = a.cross(&b1.sub(a0)).signum();
+    let a0_side = b.cross(&a0.sub(b0)).signum();
+    let a1_side = b.cross(&a1.sub(b0)).signum();
+    if b0_side * b1_side >= 0 || a0_side * a1_side >= 0 {
+        return None;
+    }
+    let t = b0.sub(a0).cross(&b) as f64 / a.cross(&b) as f64;
+    Some(Vector2D::new(
+        (a0.x as f64 + a.x as f64 * t).round() as i64,
+        (a0.y as f64 + a.y as f64 * t).round() as i64,
+    ))
+}
+
+/// Whether `point` is on the segment a---b (including its endpoints)
+fn on_segment(point: &Vector2D, a: &Vector2D, b: &Vector2D) -> bool {
+    b.sub(a).cross(&point.sub(a)) == 0
+        && point.x >= a.x.min(b.x) && point.x <= a.x.max(b.x)
+        && point.y >= a.y.min(b.y) && point.y <= a.y.max(b.y)
+}
+
+/// Finds everywhere that edges of the closed loop `vertices` touch or cross each other. Returns, for each
+/// edge (edge `i` goes from `vertices[i]` to the next vertex), the points strictly between its endpoints
+/// where another edge touches it, sorted from its start to its end.
+fn edge_contacts(vertices: &[Vector2D]) -> Vec<Vec<Vector2D>> {
+    use std::collections::{HashMap, HashSet};
+
+    let edge = |i: usize| (&vertices[i], &vertices[(i + 1) % vertices.len()]);
+    let (min_x, max_x) = (vertices.iter().map(|v| v.x).min().unwrap(), vertices.iter().map(|v| v.x).max().unwrap());
+    let (min_y, max_y) = (vertices.iter().map(|v| v.y).min().unwrap(), vertices.iter().map(|v| v.y).max().unwrap());
+    // bucket the edges into a grid of roughly as many cells as there are edges, so that only edges that
+    // are near each other get compared
+    let extent = (max_x - min_x).max(max_y - min_y) as f64;
+    let cell_size = ((extent / (vertices.len() as f64).sqrt()) as i64).max(1);
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for i in 0..vertices.len() {
+        let (a, b) = edge(i);
+        for cell_x in (a.x.min(b.x) - min_x) / cell_size..=(a.x.max(b.x) - min_x) / cell_size {
+            for cell_y in (a.y.min(b.y) - min_y) / cell_size..=(a.y.max(b.y) - min_y) / cell_size {
+                grid.entry((cell_x, cell_y)).or_default().push(i);
+            }
+        }
+    }
+
+    let mut contacts = vec![Vec::new(); vertices.len()];
+    let mut add_contact = |edge_index: usize, point: &Vector2D| {
+        let (start, end) = edge(edge_index);
+        if point != start && point != end {
+            contacts[edge_index].push(point.clone());
+        }
+    };
+    let mut tested = HashSet::new();
+    for edges in grid.values() {
+        for (n, &i) in edges.iter().enumerate() {
+            for &j in &edges[n + 1..] {
+                if !tested.insert((i.min(j), i.max(j))) {
+                    continue;
+                }
+                let ((a0, a1), (b0, b1)) = (edge(i), edge(j));
+                if let Some(point) = segment_crossing(a0, a1, b0, b1) {
+                    add_contact(i, &point);
+                    add_contact(j, &point);
+                }
+                // edges that only touch, or overlap each other
+                for (point, other) in [(a0, j), (a1, j), (b0, i), (b1, i)] {
+                    let (start, end) = edge(other);
+                    if on_segment(point, start, end) {
+                        add_contact(other, point);
+                    }
+                }
+            }
+        }
+    }
+
+    for (i, points) in contacts.iter_mut().enumerate() {
+        let (start, end) = edge(i);
+        let direction = end.sub(start);
+        points.sort_by_key(|point| point.sub(start).dot(&direction));
+        points.dedup();
+    }
+    contacts
+}
+
+/// Splits the closed loop `vertices` everywhere that it touches or crosses itself, returning loops that
+/// don't. Loops are returned without their closing vertex.
+fn split_self_intersections(vertices: &[Vector2D]) -> Vec<Vec<Vector2D>> {
+    use std::collections::HashMap;
+
+    let contacts = edge_contacts(vertices);
+    // walk the loop, and every time a point is reached for the second time, cut off the loop that
+    // was walked since the first time
+    let mut loops = Vec::new();
+    let mut path: Vec<Vector2D> = Vec::new();
+    let mut positions: HashMap<Vector2D, usize> = HashMap::new();
+    for point in vertices.iter().zip(contacts).flat_map(|(vertex, contacts)| std::iter::once(vertex.clone()).chain(contacts)) {
+        if let Some(&position) = positions.get(&point) {
+            // the point stays on the path, as the end of the part before the loop
+            let tail = path.split_off(position + 1);
+            for vertex in &tail {
+                positions.remove(vertex);
+            }
+            loops.push(std::iter::once(point).chain(tail).collect::<Vec<_>>());
+        } else {
+            positions.insert(point.clone(), path.len());
+            path.push(point);
+        }
+    }
+    loops.push(path);
+    loops.retain(|vertices| vertices.len() >= 3);
+    loops
+}
+
+/// Whether `middle` is exactly on the line from `before` to `after` (and between them), so that the path
+/// through all three goes straight through `middle`
+fn is_straight_through(before: &Vector2D, middle: &Vector2D, after: &Vector2D) -> bool {
+    let (incoming, outgoing) = (middle.sub(before), after.sub(middle));
+    incoming.cross(&outgoing) == 0 && incoming.dot(&outgoing) > 0
 }
 
 /// Builds a closed polygon.
@@ -90,18 +855,39 @@ impl PolygonBuilder {
     }
 
     /// Adds a line from the end of the previous point (or from the start point, if this is the first line)
-    /// to the point `to`.
+    /// to the point `to`. If the new line continues straight on from the previous one, the two are merged
+    /// into a single line, and a line of zero length isn't added at all.
     pub fn line_to(&mut self, to: Vector2D) {
+        if self.vertices.last() == Some(&to) {
+            return;
+        }
+        if let [.., before, last] = &self.vertices[..] {
+            if is_straight_through(before, last, &to) {
+                self.vertices.pop();
+            }
+        }
         self.vertices.push(to);
     }
 
-    /// Adds a final line to the start point, then builds the Polygon.
+    /// Adds a final line to the start point, then builds the Polygon. Like [PolygonBuilder::line_to], the
+    /// final line is merged with the lines before and after it if they're straight, so the start point might
+    /// not be a vertex of the polygon.
     pub fn close(self) -> Polygon {
         let Self {
             mut vertices,
             start_point,
         } = self;
-        vertices.push(start_point);
+        if vertices.len() > 1 && vertices.last() == Some(&start_point) {
+            vertices.pop();
+        }
+        // always keep at least a triangle
+        if vertices.len() > 3 && is_straight_through(&vertices[vertices.len() - 2], &vertices[vertices.len() - 1], &vertices[0]) {
+            vertices.pop();
+        }
+        if vertices.len() > 3 && is_straight_through(&vertices[vertices.len() - 1], &vertices[0], &vertices[1]) {
+            vertices.remove(0);
+        }
+        vertices.push(vertices[0].clone());
 
         Polygon {
             vertices,
@@ -112,3 +898,74 @@ impl PolygonBuilder {
         &self.start_point
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intersection(a0: (i64, i64), a1: (i64, i64), b0: (i64, i64), b1: (i64, i64)) -> Option<Vector2D> {
+        let point = |(x, y)| Vector2D::new(x, y);
+        segment_intersection(&point(a0), &point(a1), &point(b0), &point(b1))
+    }
+
+    #[test]
+    fn cross_sign_gives_the_turn_direction() {
+        let east = Vector2D::new(10, 0);
+        assert!(east.cross(&Vector2D::new(3, 4)) > 0);
+        assert!(east.cross(&Vector2D::new(3, -4)) < 0);
+        assert_eq!(east.cross(&Vector2D::new(-7, 0)), 0);
+        assert_eq!(Vector2D::new(2, 3).cross(&Vector2D::new(4, 6)), 0);
+    }
+
+    #[test]
+    fn distance_to() {
+        let a = Vector2D::new(1_000_000, 2_000_000);
+        let b = Vector2D::new(4_000_000, 6_000_000);
+        assert_eq!(a.distance_to(&b), 5_000_000.0);
+        assert_eq!(b.distance_to(&a), 5_000_000.0);
+        assert_eq!(a.distance_to(&a), 0.0);
+    }
+
+    #[test]
+    fn crossing_segments_intersect() {
+        assert_eq!(intersection((0, 0), (10, 10), (0, 10), (10, 0)), Some(Vector2D::new(5, 5)));
+        // rounded to the nearest nanometer
+        assert_eq!(intersection((0, 0), (3, 0), (1, -1), (2, 2)), Some(Vector2D::new(1, 0)));
+    }
+
+    #[test]
+    fn touching_segments_intersect_at_the_touching_endpoint() {
+        // T-touch
+        assert_eq!(intersection((0, 0), (10, 0), (4, 0), (4, 7)), Some(Vector2D::new(4, 0)));
+        assert_eq!(intersection((4, 7), (4, 0), (0, 0), (10, 0)), Some(Vector2D::new(4, 0)));
+        // shared endpoint
+        assert_eq!(intersection((0, 0), (10, 0), (10, 0), (10, 10)), Some(Vector2D::new(10, 0)));
+        assert_eq!(intersection((0, 0), (10, 0), (0, 0), (-5, -5)), Some(Vector2D::new(0, 0)));
+    }
+
+    #[test]
+    fn collinear_segments() {
+        // overlapping: the end of the overlap nearest to a0
+        assert_eq!(intersection((0, 0), (10, 0), (4, 0), (20, 0)), Some(Vector2D::new(4, 0)));
+        assert_eq!(intersection((10, 10), (0, 0), (-5, -5), (7, 7)), Some(Vector2D::new(7, 7)));
+        assert_eq!(intersection((0, 0), (10, 0), (2, 0), (8, 0)), Some(Vector2D::new(2, 0)));
+        // disjoint
+        assert_eq!(intersection((0, 0), (10, 0), (11, 0), (20, 0)), None);
+        assert_eq!(intersection((0, 0), (3, 3), (4, 4), (9, 9)), None);
+    }
+
+    #[test]
+    fn parallel_segments_dont_intersect() {
+        assert_eq!(intersection((0, 0), (10, 0), (0, 1), (10, 1)), None);
+        assert_eq!(intersection((0, 0), (10, 10), (1, 0), (11, 10)), None);
+    }
+
+    #[test]
+    fn zero_length_segments() {
+        assert_eq!(intersection((5, 0), (5, 0), (0, 0), (10, 0)), Some(Vector2D::new(5, 0)));
+        assert_eq!(intersection((0, 0), (10, 0), (5, 0), (5, 0)), Some(Vector2D::new(5, 0)));
+        assert_eq!(intersection((5, 1), (5, 1), (0, 0), (10, 0)), None);
+        assert_eq!(intersection((3, 3), (3, 3), (3, 3), (3, 3)), Some(Vector2D::new(3, 3)));
+        assert_eq!(intersection((3, 3), (3, 3), (4, 4), (4, 4)), None);
+    }
+}
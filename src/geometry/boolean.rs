@@ -0,0 +1,382 @@
+//! Boolean operations between areas, each made up of [Region]s: outlines with holes cut out of them.
+//!
+//! The operations work by splitting the edges of both areas wherever they cross or touch, keeping the pieces
+//! that are on the boundary of the result (decided by whether they are inside the other area), and joining the
+//! kept edges back up into polygons. Coordinates stay integers; only the points where edges cross are rounded.
+
+use std::collections::{HashMap, HashSet};
+use super::{on_segment, segment_crossing, Polygon, Vector2D};
+
+/// An area bounded by a counterclockwise outline, minus the areas inside clockwise holes. This is the same
+/// shape as a [SliceIsland](crate::slice::SliceIsland), which it can be converted into.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub outline: Polygon,
+    pub holes: Vec<Polygon>,
+}
+
+impl Region {
+    /// Whether `point` is inside the outline and not inside any of the holes. Points on the outline are
+    /// inside, and points on a hole are not.
+    pub fn contains(&self, point: &Vector2D) -> bool {
+        self.outline.contains(point) && !self.holes.iter().any(|hole| hole.contains(point))
+    }
+}
+
+/// The area covered by `a`, `b`, or both.
+///
+/// The regions within each of `a` and `b` must not overlap each other, and their polygons must not cross
+/// themselves (which is true of the islands of a slice). Outlines and holes can go either way around.
+pub fn union(a: &[Region], b: &[Region]) -> Vec<Region> {
+    combine(a, b, Operation::Union)
+}
+
+/// The area covered by both `a` and `b`. The same requirements as [union] apply.
+pub fn intersection(a: &[Region], b: &[Region]) -> Vec<Region> {
+    combine(a, b, Operation::Intersection)
+}
+
+/// The area covered by `a` but not `b`. The same requirements as [union] apply.
+pub fn difference(a: &[Region], b: &[Region]) -> Vec<Region> {
+    combine(a, b, Operation::Difference)
+}
+
+#[derive(Copy, Clone)]
+enum Operation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+/// A directed edge, from its first point to its second
+type Edge = (Vector2D, Vector2D);
+
+fn combine(a: &[Region], b: &[Region], operation: Operation) -> Vec<Region> {
+    let (a_edges, b_edges) = split_edges(&edges(a), &edges(b));
+    let a_set: HashSet<&Edge> = a_edges.iter().collect();
+    let b_set: HashSet<&Edge> = b_edges.iter().collect();
+
+    // Each kept edge goes with the result's interior on its left. An edge of both areas going the same way has
+    // both interiors on the same side, and one going opposite ways has them on opposite sides.
+    let mut kept = Vec::new();
+    for (start, end) in &a_edges {
+        let keep = if b_set.contains(&(start.clone(), end.clone())) {
+            matches!(operation, Operation::Union | Operation::Intersection)
+        } else if b_set.contains(&(end.clone(), start.clone())) {
+            matches!(operation, Operation::Difference)
+        } else {
+            let inside = contains_midpoint(b, start, end);
+            match operation {
+                Operation::Union | Operation::Difference => !inside,
+                Operation::Intersection => inside,
+            }
+        };
+        if keep {
+            kept.push((start.clone(), end.clone()));
+        }
+    }
+    for (start, end) in &b_edges {
+        // shared edges were already handled with the edges of `a`
+        if a_set.contains(&(start.clone(), end.clone())) || a_set.contains(&(end.clone(), start.clone())) {
+            continue;
+        }
+        let inside = contains_midpoint(a, start, end);
+        match operation {
+            Operation::Union if !inside => kept.push((start.clone(), end.clone())),
+            Operation::Intersection if inside => kept.push((start.clone(), end.clone())),
+            // the part of `a` that is cut away by `b` is on the other side of `b`'s boundary
+            Operation::Difference if inside => kept.push((end.clone(), start.clone())),
+            _ => {},
+        }
+    }
+
+    nest(stitch(kept))
+}
+
+/// All the edges of `regions`, with outlines going counterclockwise and holes clockwise
+fn edges(regions: &[Region]) -> Vec<Edge> {
+    let mut edges = Vec::new();
+    for region in regions {
+        let loops = std::iter::once((&region.outline, false)).chain(region.holes.iter().map(|hole| (hole, true)));
+        for (polygon, is_hole) in loops {
+            let vertices = polygon.vertices();
+            let forwards = polygon.is_clockwise() == is_hole;
+            for edge in vertices.windows(2).filter(|edge| edge[0] != edge[1]) {
+                let (start, end) = (edge[0].clone(), edge[1].clone());
+                edges.push(if forwards { (start, end) } else { (end, start) });
+            }
+        }
+    }
+    edges
+}
+
+/// Splits the edges of `a` and of `b` at every point where an edge of the other one crosses or touches them,
+/// so that afterwards edges of `a` and `b` only meet at their endpoints, and edges that overlap are identical
+fn split_edges(a: &[Edge], b: &[Edge]) -> (Vec<Edge>, Vec<Edge>) {
+    let mut a_points = vec![Vec::new(); a.len()];
+    let mut b_points = vec![Vec::new(); b.len()];
+    for (i, (a0, a1)) in a.iter().enumerate() {
+        for (j, (b0, b1)) in b.iter().enumerate() {
+            let overlaps = a0.x.min(a1.x) <= b0.x.max(b1.x) && b0.x.min(b1.x) <= a0.x.max(a1.x)
+                && a0.y.min(a1.y) <= b0.y.max(b1.y) && b0.y.min(b1.y) <= a0.y.max(a1.y);
+            if !overlaps {
+                continue;
+            }
+            if let Some(crossing) = canonical_crossing(a0, a1, b0, b1) {
+                a_points[i].push(crossing.clone());
+                b_points[j].push(crossing);
+            }
+            for point in [b0, b1] {
+                if on_segment(point, a0, a1) {
+                    a_points[i].push(point.clone());
+                }
+            }
+            for point in [a0, a1] {
+                if on_segment(point, b0, b1) {
+                    b_points[j].push(point.clone());
+                }
+            }
+        }
+    }
+    (split_at(a, a_points), split_at(b, b_points))
+}
+
+/// Where two segments cross, rounded the same way no matter which order the segments (and their endpoints)
+/// are given in, so that both segments get split at exactly the same point
+fn canonical_crossing(a0: &Vector2D, a1: &Vector2D, b0: &Vector2D, b1: &Vector2D) -> Option<Vector2D> {
+    let key = |point: &Vector2D| (point.x, point.y);
+    let sorted = |p: &'_ Vector2D, q: &'_ Vector2D| if key(p) <= key(q) { (p.clone(), q.clone()) } else { (q.clone(), p.clone()) };
+    let (a, b) = (sorted(a0, a1), sorted(b0, b1));
+    let (first, second) = if (key(&a.0), key(&a.1)) <= (key(&b.0), key(&b.1)) { (a, b) } else { (b, a) };
+    segment_crossing(&first.0, &first.1, &second.0, &second.1)
+}
+
+/// Splits each of `edges` at its `points`, leaving out any pieces of zero length
+fn split_at(edges: &[Edge], points: Vec<Vec<Vector2D>>) -> Vec<Edge> {
+    let mut pieces = Vec::new();
+    for ((start, end), mut points) in edges.iter().zip(points) {
+        let direction = end.sub(start);
+        points.sort_by_key(|point| point.sub(start).dot(&direction));
+        let mut previous = start;
+        for point in points.iter().chain(std::iter::once(end)) {
+            if point != previous {
+                pieces.push((previous.clone(), point.clone()));
+                previous = point;
+            }
+        }
+    }
+    pieces
+}
+
+/// Whether the midpoint of the segment from `start` to `end` is inside any of `regions`, checked exactly
+/// (without rounding the midpoint)
+fn contains_midpoint(regions: &[Region], start: &Vector2D, end: &Vector2D) -> bool {
+    // work in doubled coordinates, where the midpoint is a whole number
+    let midpoint = (start.x as i128 + end.x as i128, start.y as i128 + end.y as i128);
+    let polygon_contains = |polygon: &Polygon| {
+        let mut inside = false;
+        for edge in polygon.vertices().windows(2) {
+            let (ax, ay) = (2 * edge[0].x as i128, 2 * edge[0].y as i128);
+            let (bx, by) = (2 * edge[1].x as i128, 2 * edge[1].y as i128);
+            // count crossings of a ray going in the +x direction, like Polygon::contains
+            if (ay > midpoint.1) != (by > midpoint.1) {
+                // whether the edge crosses the ray's line to the right of the midpoint, without dividing
+                let height = by - ay;
+                let crossing_offset = ax * height + (midpoint.1 - ay) * (bx - ax) - midpoint.0 * height;
+                if (crossing_offset > 0) == (height > 0) {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    };
+    regions.iter().any(|region| polygon_contains(&region.outline) && !region.holes.iter().any(polygon_contains))
+}
+
+/// Joins directed edges end to start into closed polygons. Where more than one edge continues from the same
+/// point, the one turning furthest left is taken, so that polygons meeting at a point are kept separate.
+fn stitch(edges: Vec<Edge>) -> Vec<Polygon> {
+    let mut outgoing: HashMap<&Vector2D, Vec<usize>> = HashMap::new();
+    for (index, (start, _)) in edges.iter().enumerate() {
+        outgoing.entry(start).or_default().push(index);
+    }
+    let mut used = vec![false; edges.len()];
+    let mut polygons = Vec::new();
+    for first in 0..edges.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let (start, mut end) = (&edges[first].0, &edges[first].1);
+        let mut direction = end.sub(start);
+        let mut builder = Polygon::builder(start.clone());
+        let mut closed = false;
+        loop {
+            if end == start {
+                closed = true;
+                break;
+            }
+            builder.line_to(end.clone());
+            let next = outgoing.get(end).into_iter().flatten()
+                .filter(|&&index| !used[index])
+                .max_by(|&&p, &&q| {
+                    let turn = |index: usize| {
+                        let next_direction = edges[index].1.sub(&edges[index].0);
+                        let angle = (direction.cross(&next_direction) as f64).atan2(direction.dot(&next_direction) as f64);
+                        // turning right around is the last resort
+                        if angle == std::f64::consts::PI { -angle } else { angle }
+                    };
+                    turn(p).total_cmp(&turn(q))
+                });
+            let Some(&next) = next else { break };
+            used[next] = true;
+            direction = edges[next].1.sub(&edges[next].0);
+            end = &edges[next].1;
+        }
+        if closed {
+            let polygon = builder.close();
+            // loops that double back on themselves enclose nothing
+            if polygon.vertices().len() > 3 && polygon.signed_area() != 0.0 {
+                polygons.push(polygon);
+            }
+        }
+    }
+    polygons
+}
+
+/// Groups polygons that don't cross each other into regions: polygons inside an even number of others are
+/// outlines, and the rest are holes in the smallest outline around them. The polygons are reversed if needed
+/// so that outlines go counterclockwise and holes clockwise.
+pub(crate) fn nest(polygons: Vec<Polygon>) -> Vec<Region> {
+    let containers: Vec<Vec<usize>> = polygons.iter().enumerate()
+        .map(|(index, polygon)| {
+            (0..polygons.len())
+                .filter(|&other| other != index && is_inside(polygon, &polygons[other]))
+                .collect()
+        })
+        .collect();
+
+    let mut regions = Vec::new();
+    // which region each polygon became the outline of, if it is an outline
+    let mut region_indices = vec![None; polygons.len()];
+    let mut holes = Vec::new();
+    for (index, mut polygon) in polygons.into_iter().enumerate() {
+        if containers[index].len() % 2 == 0 {
+            if polygon.is_clockwise() {
+                polygon.reverse();
+            }
+            region_indices[index] = Some(regions.len());
+            regions.push(Region { outline: polygon, holes: Vec::new() });
+        } else {
+            if !polygon.is_clockwise() {
+                polygon.reverse();
+            }
+            holes.push((index, polygon));
+        }
+    }
+
+    for (index, hole) in holes {
+        // the smallest container is the one that is itself inside all of the others
+        let depth = containers[index].len();
+        let parent = containers[index].iter()
+            .find(|&&container| containers[container].len() == depth - 1)
+            .and_then(|&container| region_indices[container]);
+        if let Some(parent) = parent {
+            regions[parent].holes.push(hole);
+        }
+    }
+
+    regions
+}
+
+/// Whether `polygon` is inside `other`, given that they don't cross. They can touch, so this goes by a vertex
+/// that isn't on `other`'s boundary.
+fn is_inside(polygon: &Polygon, other: &Polygon) -> bool {
+    polygon.vertices().iter()
+        .find(|vertex| !other.vertices().windows(2).any(|edge| on_segment(vertex, &edge[0], &edge[1])))
+        .is_some_and(|vertex| other.contains(vertex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A counterclockwise rectangle from `(x0, y0)` to `(x1, y1)`
+    fn rectangle(x0: i64, y0: i64, x1: i64, y1: i64) -> Polygon {
+        let mut builder = Polygon::builder(Vector2D::new(x0, y0));
+        builder.line_to(Vector2D::new(x1, y0));
+        builder.line_to(Vector2D::new(x1, y1));
+        builder.line_to(Vector2D::new(x0, y1));
+        builder.close()
+    }
+
+    fn region(outline: Polygon, holes: Vec<Polygon>) -> Vec<Region> {
+        vec![Region { outline, holes }]
+    }
+
+    /// The number of regions, the number of holes in each, and the total area
+    fn shape(regions: &[Region]) -> (usize, Vec<usize>, f64) {
+        let mut holes: Vec<usize> = regions.iter().map(|region| region.holes.len()).collect();
+        holes.sort();
+        let area = regions.iter()
+            .map(|region| region.outline.signed_area().abs() - region.holes.iter().map(|hole| hole.signed_area().abs()).sum::<f64>())
+            .sum();
+        (regions.len(), holes, area)
+    }
+
+    #[test]
+    fn overlapping_squares() {
+        let (a, b) = (region(rectangle(0, 0, 10, 10), Vec::new()), region(rectangle(5, 5, 15, 15), Vec::new()));
+        assert_eq!(shape(&union(&a, &b)), (1, vec![0], 175.0));
+        assert_eq!(shape(&intersection(&a, &b)), (1, vec![0], 25.0));
+        assert_eq!(shape(&difference(&a, &b)), (1, vec![0], 75.0));
+        assert_eq!(shape(&difference(&b, &a)), (1, vec![0], 75.0));
+    }
+
+    #[test]
+    fn square_with_a_hole() {
+        let mut hole = rectangle(10, 10, 20, 20);
+        hole.reverse();
+        let ring = region(rectangle(0, 0, 30, 30), vec![hole]);
+        let plug = region(rectangle(5, 5, 25, 25), Vec::new());
+        // the plug fills in the hole
+        assert_eq!(shape(&union(&ring, &plug)), (1, vec![0], 900.0));
+        assert_eq!(shape(&intersection(&ring, &plug)), (1, vec![1], 300.0));
+        assert_eq!(shape(&difference(&ring, &plug)), (1, vec![1], 500.0));
+        assert_eq!(shape(&difference(&plug, &ring)), (1, vec![0], 100.0));
+        assert!(difference(&plug, &ring)[0].contains(&Vector2D::new(15, 15)));
+    }
+
+    #[test]
+    fn edges_shared_in_the_same_direction() {
+        // the bottom half of `a`, sharing its bottom edge and the lower halves of its sides
+        let (a, b) = (region(rectangle(0, 0, 10, 10), Vec::new()), region(rectangle(0, 0, 10, 5), Vec::new()));
+        assert_eq!(shape(&union(&a, &b)), (1, vec![0], 100.0));
+        assert_eq!(shape(&intersection(&a, &b)), (1, vec![0], 50.0));
+        let top = difference(&a, &b);
+        assert_eq!(shape(&top), (1, vec![0], 50.0));
+        assert!(top[0].contains(&Vector2D::new(5, 8)) && !top[0].contains(&Vector2D::new(5, 2)));
+        assert!(difference(&b, &a).is_empty());
+    }
+
+    #[test]
+    fn edges_shared_in_opposite_directions() {
+        // side by side, so the shared edge goes up one side of `a` and down one side of `b`
+        let (a, b) = (region(rectangle(0, 0, 10, 10), Vec::new()), region(rectangle(10, 0, 20, 10), Vec::new()));
+        let both = union(&a, &b);
+        assert_eq!(shape(&both), (1, vec![0], 200.0));
+        // the shared edge is inside the union
+        assert!(!both[0].outline.vertices().iter().any(|vertex| vertex.x == 10));
+        assert!(intersection(&a, &b).is_empty());
+        assert_eq!(shape(&difference(&a, &b)), (1, vec![0], 100.0));
+    }
+
+    #[test]
+    fn polygons_touching_at_a_vertex() {
+        let (a, b) = (region(rectangle(0, 0, 10, 10), Vec::new()), region(rectangle(10, 10, 20, 20), Vec::new()));
+        // the two squares stay separate regions
+        assert_eq!(shape(&union(&a, &b)), (2, vec![0, 0], 200.0));
+        assert!(intersection(&a, &b).is_empty());
+        assert_eq!(shape(&difference(&a, &b)), (1, vec![0], 100.0));
+    }
+}
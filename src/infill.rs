@@ -0,0 +1,124 @@
+use crate::geometry::{Polygon, Vector2D};
+
+/// Returns the ranges along a scanline that are inside the area bounded by `boundary` (using the even-odd rule).
+///
+/// The scanline is the horizontal line at y=`position`, or the vertical line at x=`position` if `vertical` is set.
+/// Each range is given as `(start, end)` coordinates along the scanline, in increasing order.
+pub(crate) fn scanline_spans(boundary: &[Polygon], position: i64, vertical: bool) -> Vec<(i64, i64)> {
+    // (along, across) coordinates of a vertex relative to the scanline direction
+    let coords = |vertex: &Vector2D| if vertical { (vertex.y, vertex.x) } else { (vertex.x, vertex.y) };
+
+    let mut crossings = Vec::new();
+    for polygon in boundary {
+        for edge in polygon.vertices().windows(2) {
+            let (a_along, a_across) = coords(&edge[0]);
+            let (b_along, b_across) = coords(&edge[1]);
+            // half-open comparison so that a vertex exactly on the scanline is only counted once
+            if (a_across <= position) != (b_across <= position) {
+                let ratio = (position - a_across) as f64 / (b_across - a_across) as f64;
+                crossings.push(a_along + ((b_along - a_along) as f64 * ratio).round() as i64);
+            }
+        }
+    }
+    crossings.sort_unstable();
+
+    crossings.chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .filter(|(start, end)| start < end)
+        .collect()
+}
+
+/// The parts of the spans `a` that are also in `b`. Both must be sorted and not overlap each other, as
+/// returned by [scanline_spans].
+pub(crate) fn intersect_spans(a: &[(i64, i64)], b: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if start < end {
+            result.push((start, end));
+        }
+        // whichever span ends first can't overlap anything else
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+/// The parts of the spans `a` that aren't in `b`. Both must be sorted and not overlap each other, as
+/// returned by [scanline_spans].
+pub(crate) fn subtract_spans(a: &[(i64, i64)], b: &[(i64, i64)]) -> Vec<(i64, i64)> {
+    let mut result = Vec::new();
+    let mut j = 0;
+    for &(mut start, end) in a {
+        // skip the spans of `b` that end before this span starts
+        while j < b.len() && b[j].1 <= start {
+            j += 1;
+        }
+        let mut k = j;
+        while k < b.len() && b[k].0 < end {
+            if b[k].0 > start {
+                result.push((start, b[k].0));
+            }
+            start = start.max(b[k].1);
+            k += 1;
+        }
+        if start < end {
+            result.push((start, end));
+        }
+    }
+    result
+}
+
+/// Generates parallel infill lines `spacing` apart covering the area bounded by `boundary`. Lines are
+/// horizontal, or vertical if `vertical` is set.
+///
+/// `clip` is called with each scanline's position and the spans of it inside `boundary`, and returns
+/// which parts of them to actually fill, e.g. to only fill part of the area.
+///
+/// Scanlines are placed at multiples of `spacing` so that lines of different layers line up. Every other
+/// scanline is reversed so the lines can be printed back and forth with short travels in between.
+pub(crate) fn rectilinear(
+    boundary: &[Polygon],
+    spacing: i64,
+    vertical: bool,
+    clip: impl Fn(i64, Vec<(i64, i64)>) -> Vec<(i64, i64)>,
+) -> Vec<[Vector2D; 2]> {
+    let across = |vertex: &Vector2D| if vertical { vertex.x } else { vertex.y };
+    let all_across = || boundary.iter().flat_map(|polygon| polygon.vertices()).map(across);
+    let (min, max) = match (all_across().min(), all_across().max()) {
+        (Some(min), Some(max)) => (min, max),
+        _ => return Vec::new(),
+    };
+
+    let point = |along: i64, across: i64| {
+        if vertical {
+            Vector2D::new(across, along)
+        } else {
+            Vector2D::new(along, across)
+        }
+    };
+
+    let mut lines = Vec::new();
+    let first_scanline = min.div_euclid(spacing) + 1;
+    let last_scanline = max.div_euclid(spacing);
+    for scanline in first_scanline..=last_scanline {
+        let position = scanline * spacing;
+        let mut spans = clip(position, scanline_spans(boundary, position, vertical));
+        if scanline % 2 != 0 {
+            spans.reverse();
+            for span in &mut spans {
+                *span = (span.1, span.0);
+            }
+        }
+        for (start, end) in spans {
+            lines.push([point(start, position), point(end, position)]);
+        }
+    }
+
+    lines
+}
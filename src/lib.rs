@@ -4,22 +4,83 @@ pub mod parsing;
 pub mod mesh;
 pub mod slice;
 pub mod gcode;
+mod infill;
+mod combing;
+mod png;
+/// Slicing and printing settings
+pub mod config;
+
+pub use config::{AccelerationConfig, AdaptiveLayerHeight, ConfigProfile, ConfigProfileBuilder, JerkConfig, RetractionConfig};
 
 #[derive(Debug)]
 pub enum Error {
     /// Error parsing a mesh file (STL, OBJ, etc)
     MeshFileParse,
-    /// Attempted to slice a scene with no meshes in it
+    /// Attempted to slice a scene with no meshes in it, or to make a [FacetFilter](mesh::FacetFilter) with no facets
     EmptyScene,
-    /// Tried to stitch a set of segments that formed a non-closed polygon
-    OpenStitchPolygon,
+    /// The outline of the mesh at a slicing plane doesn't close up, which happens when the mesh has a hole in
+    /// it (see [mesh::Mesh::non_manifold_edges]). Contains the height of the plane, where the two ends of the
+    /// unclosed chain of outline segments are, and how many segments are in it.
+    OpenStitchPolygon {
+        z: i64,
+        ends: [geometry::Vector2D; 2],
+        segments: usize,
+    },
+    /// A facet crossed a slicing plane at other than exactly two points, which happens with malformed
+    /// facets (e.g. ones whose vertices are all on one line)
+    FacetIntersection,
+    /// A [ConfigProfile] setting has an invalid value. The string describes which one and why.
+    InvalidConfig(&'static str),
+    /// A serialized [ConfigProfile] couldn't be loaded. The string describes what was wrong with it.
+    ConfigParse(String),
+    /// A facet given to [mesh::Mesh::from_indexed] refers to a vertex index that isn't in the vertex list
+    InvalidMeshIndex(usize),
+    /// A position in the generated G-code (e.g. the height of a layer or the total length of filament
+    /// extruded) is too big to represent
+    CoordinateOverflow,
+    /// A layer has more than one contour (island or hole), which can't be printed as a single spiral in
+    /// [ConfigProfile::vase_mode]. Contains the index of the layer.
+    VaseModeMultipleContours(usize),
+    /// Error parsing G-code (see [gcode::parse]). Contains the line number.
+    GCodeParse(usize),
+    /// A polygon's edges cross or touch each other, which the operation needs them not to do (see
+    /// [geometry::Polygon::triangulate])
+    SelfIntersectingPolygon,
+    /// Slicing was cancelled (see [slice::Slicer::with_cancel_flag])
+    Cancelled,
+    /// The scene doesn't fit in the build volume (see [mesh::Scene::fit_check]). `position` is the lowest or
+    /// highest coordinate of the scene along `axis` (in nanometers), whichever is outside the volume.
+    OutOfBounds {
+        axis: geometry::Axis,
+        position: i64,
+    },
+}
+
+/// A problem that doesn't stop slicing, but probably means the result isn't what was intended
+/// (see [slice::Slicer::slice_with_warnings])
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// No part of the model is between these heights (in nanometers), but there is model both above and
+    /// below, so whatever is above is printed in midair. Usually the model was meant to be in one piece, or
+    /// an object on the plate was meant to rest on the bed.
+    EmptyLayers {
+        bottom: i64,
+        top: i64,
+    },
+    /// [ConfigProfile::layer_height] is more than half as tall as the whole model (both in nanometers), so
+    /// the model only gets one or two layers. Usually the layer height was given in the wrong units.
+    LayerHeightTooLarge {
+        layer_height: u64,
+        model_height: u64,
+    },
 }
 
-/// Global configuration
-pub struct ConfigProfile {
-    /// Thickness of each printed slice (in nanometers)
-    pub layer_height: u64,
-    pub hotend_temperature: u32,
-    /// Speed to move when not extruding
-    pub travel_speed: u32,
+/// Slices a mesh file (see [parsing::parse_mesh_file]) and generates G-code to print it, all in one go.
+/// The file format is detected with [parsing::detect_stl_type].
+pub fn slice_file(bytes: &[u8], units: parsing::MeshFileUnits, config: &ConfigProfile) -> Result<String, Error> {
+    let mesh = parsing::parse_mesh_file(bytes, parsing::detect_stl_type(bytes), units)?;
+    let mut scene = mesh::Scene::new();
+    scene.add_mesh(mesh);
+    let slices = slice::Slicer::new(config).slice(scene)?;
+    gcode::slices_to_gcode(config, &slices)
 }
@@ -1,15 +1,106 @@
-use crate::geometry::Vector3D;
+use std::collections::{BTreeMap, HashMap};
+use crate::geometry::{Axis, BoundingBox3D, Vector2D, Vector3D, NANOS_PER_MILLIMETER};
+use crate::Error;
 
 /// Traingle face of a mesh
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Facet {
     vertices: [Vector3D; 3],
+    /// Outward-facing normal. Only the direction is meaningful, see [Facet::computed_normal].
+    normal: Vector3D,
+    /// The "attribute byte count" of the facet in a binary STL, which some programs use to store a color.
+    /// `None` if it was zero or there isn't one.
+    attribute: Option<u16>,
 }
 
 impl Facet {
+    /// Creates a facet whose normal is computed from the winding order of `vertices`
     pub fn new(vertices: [Vector3D; 3]) -> Self {
+        let mut facet = Self {
+            vertices,
+            normal: Vector3D::new(0, 0, 0),
+            attribute: None,
+        };
+        facet.normal = facet.computed_normal();
+        facet
+    }
+
+    /// Creates a facet with an explicitly given normal (e.g. the one stored in a mesh file)
+    pub fn with_normal(vertices: [Vector3D; 3], normal: Vector3D) -> Self {
         Self {
             vertices,
+            normal,
+            attribute: None,
+        }
+    }
+
+    /// Sets the attribute of this facet (see [Facet::attribute]). Zero is the same as no attribute.
+    pub fn with_attribute(mut self, attribute: u16) -> Self {
+        self.attribute = (attribute != 0).then_some(attribute);
+        self
+    }
+
+    /// The raw 16-bit attribute stored for this facet in a binary STL file, or `None` if it was zero (which is
+    /// what nearly every program writes) or the facet didn't come from a binary STL
+    pub fn attribute(&self) -> Option<u16> {
+        self.attribute
+    }
+
+    /// The color of this facet as `[red, green, blue]` (each from 0 to 255), decoded from its attribute
+    /// in the common 5-5-5 format used by VisCAM and SolidView: blue in the lowest 5 bits, then green,
+    /// then red, with the highest bit set if the color is valid. `None` if the facet has no valid color.
+    pub fn color(&self) -> Option<[u8; 3]> {
+        let attribute = self.attribute.filter(|attribute| attribute & 0x8000 != 0)?;
+        // scale each 5-bit channel up to 8 bits, so that 31 becomes 255
+        let channel = |shift: u16| {
+            let value = (attribute >> shift) & 0x1f;
+            ((value << 3) | (value >> 2)) as u8
+        };
+        Some([channel(10), channel(5), channel(0)])
+    }
+
+    /// The three corners of this facet, in winding order
+    pub fn vertices(&self) -> &[Vector3D; 3] {
+        &self.vertices
+    }
+
+    /// The normal stored for this facet
+    pub fn normal(&self) -> &Vector3D {
+        &self.normal
+    }
+
+    /// Computes the normal of this facet from its winding order, using the right-hand rule:
+    /// `(v1 - v0) x (v2 - v0)`.
+    ///
+    /// The result is unnormalized - since coordinates are integer nanometers its magnitude
+    /// is huge (twice the facet's area), so it should only be used for direction/sign comparisons. For facets
+    /// too big for that to fit in an `i64` (with edges longer than about 2 meters), it is divided by a power
+    /// of two until it fits.
+    pub fn computed_normal(&self) -> Vector3D {
+        let [v0, v1, v2] = &self.vertices;
+        let normal = v1.sub(v0).wide_cross(&v2.sub(v0));
+        let largest = normal.iter().map(|component| component.unsigned_abs()).max().unwrap();
+        let shift = (u128::BITS - largest.leading_zeros()).saturating_sub(i64::BITS - 1);
+        let [x, y, z] = normal.map(|component| (component >> shift) as i64);
+        Vector3D::new(x, y, z)
+    }
+
+    /// Whether the facet has no area, i.e. its vertices are all on one line (which includes facets
+    /// with two or three identical vertices). Such facets can't be sliced.
+    pub fn is_degenerate(&self) -> bool {
+        let [v0, v1, v2] = &self.vertices;
+        v1.sub(v0).wide_cross(&v2.sub(v0)) == [0; 3]
+    }
+
+    /// [Facet::computed_normal], scaled to a length of 1. Zero if the facet is degenerate.
+    fn unit_normal(&self) -> [f64; 3] {
+        let normal = self.computed_normal();
+        let (x, y, z) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let length = x.hypot(y).hypot(z);
+        if length == 0.0 {
+            [0.0; 3]
+        } else {
+            [x / length, y / length, z / length]
         }
     }
 
@@ -19,6 +110,55 @@ impl Facet {
         }
     }
 
+    fn rotate(&mut self, axis: Axis, radians: f64) {
+        for vertex in &mut self.vertices {
+            *vertex = vertex.rotated(axis, radians);
+        }
+        self.normal = self.normal.rotated(axis, radians);
+    }
+
+    fn scale(&mut self, factors: [f64; 3]) {
+        for vertex in &mut self.vertices {
+            *vertex = Vector3D::new(
+                (vertex.x as f64 * factors[0]).round() as i64,
+                (vertex.y as f64 * factors[1]).round() as i64,
+                (vertex.z as f64 * factors[2]).round() as i64,
+            );
+        }
+        // normals scale by the inverse of the factors. The result is brought back to the normal's original
+        // magnitude so that repeated scaling can't overflow it.
+        let normal = [
+            self.normal.x as f64 / factors[0],
+            self.normal.y as f64 / factors[1],
+            self.normal.z as f64 / factors[2],
+        ];
+        let old_magnitude = self.normal.x.abs().max(self.normal.y.abs()).max(self.normal.z.abs()) as f64;
+        let new_magnitude = normal.iter().fold(0.0f64, |max, component| max.max(component.abs()));
+        if new_magnitude > 0.0 && new_magnitude.is_finite() {
+            let ratio = old_magnitude / new_magnitude;
+            self.normal = Vector3D::new(
+                (normal[0] * ratio).round() as i64,
+                (normal[1] * ratio).round() as i64,
+                (normal[2] * ratio).round() as i64,
+            );
+        }
+    }
+
+    /// Negates the `axis` coordinate of the vertices and normal, and reverses the winding order so the
+    /// normal still agrees with it
+    fn mirror(&mut self, axis: Axis) {
+        let mirrored = |vector: &Vector3D| match axis {
+            Axis::X => Vector3D::new(-vector.x, vector.y, vector.z),
+            Axis::Y => Vector3D::new(vector.x, -vector.y, vector.z),
+            Axis::Z => Vector3D::new(vector.x, vector.y, -vector.z),
+        };
+        for vertex in &mut self.vertices {
+            *vertex = mirrored(vertex);
+        }
+        self.normal = mirrored(&self.normal);
+        self.vertices.swap(1, 2);
+    }
+
     /// The lowest z value of all the facet's vertices
     fn lower_z_bound(&self) -> i64 {
         // the unwrap is ok because we know that `vertices` isn't empty
@@ -32,47 +172,501 @@ impl Facet {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Mesh {
     facets: Vec<Facet>,
+    /// Which facets share vertices and edges, built by [Mesh::weld_vertices]
+    topology: Option<MeshTopology>,
+    /// See [Mesh::tool]
+    tool: u8,
 }
 
 impl Mesh {
     pub fn new(facets: Vec<Facet>) -> Self {
-        Self { facets }
+        Self { facets, topology: None, tool: 0 }
+    }
+
+    /// The number of the extruder that prints this mesh, 0 unless set with [Mesh::set_tool]. The islands
+    /// sliced from it keep this number (see [SliceIsland::tool](crate::slice::SliceIsland::tool)).
+    pub fn tool(&self) -> u8 {
+        self.tool
+    }
+
+    /// Sets which extruder prints this mesh, for printers with more than one. Meshes printed by different
+    /// tools are sliced separately, so they shouldn't overlap.
+    pub fn set_tool(&mut self, tool: u8) {
+        self.tool = tool;
+    }
+
+    /// Creates a mesh from a list of vertices and the indices of each facet's vertices in that list, e.g. from
+    /// generated geometry or another 3D format. Facet normals are computed from the winding order of
+    /// the indices (see [Facet::computed_normal]).
+    ///
+    /// Returns [Error::InvalidMeshIndex] if any index is out of bounds of `vertices`.
+    pub fn from_indexed(vertices: &[Vector3D], indices: &[[usize; 3]]) -> Result<Self, Error> {
+        let facets = indices.iter()
+            .map(|facet_indices| {
+                let [a, b, c] = facet_indices.map(|index| vertices.get(index).cloned().ok_or(Error::InvalidMeshIndex(index)));
+                Ok(Facet::new([a?, b?, c?]))
+            })
+            .collect::<Result<Vec<Facet>, Error>>()?;
+        Ok(Self::new(facets))
+    }
+
+    /// Encodes the mesh as a binary STL file, in millimeters. Normals are computed from the winding order of
+    /// each facet's vertices (see [Facet::computed_normal]).
+    pub fn to_binary_stl(&self) -> Vec<u8> {
+        const HEADER: &[u8] = b"binary STL written by ddd";
+        /// Defined by the STL standard
+        const HEADER_LENGTH: usize = 80;
+        /// Normal and three vertices of three f32s each, and a u16 attribute
+        const FACET_LENGTH: usize = 4 * 3 * 4 + 2;
+
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + 4 + self.facets.len() * FACET_LENGTH);
+        bytes.extend_from_slice(HEADER);
+        bytes.resize(HEADER_LENGTH, 0);
+        bytes.extend_from_slice(&(self.facets.len() as u32).to_le_bytes());
+        for facet in &self.facets {
+            for component in facet.unit_normal() {
+                bytes.extend_from_slice(&(component as f32).to_le_bytes());
+            }
+            for vertex in &facet.vertices {
+                for coordinate in [vertex.x, vertex.y, vertex.z] {
+                    bytes.extend_from_slice(&((coordinate as f64 / NANOS_PER_MILLIMETER) as f32).to_le_bytes());
+                }
+            }
+            // attribute byte count, which may hold a color
+            bytes.extend_from_slice(&facet.attribute.unwrap_or(0).to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Encodes the mesh as an ASCII STL file, in millimeters, with the solid called `name`. Normals are computed
+    /// from the winding order of each facet's vertices (see [Facet::computed_normal]).
+    pub fn to_ascii_stl(&self, name: &str) -> String {
+        use std::fmt::Write;
+
+        let mut stl = String::new();
+        // writing to a String can't fail
+        writeln!(stl, "solid {}", name).unwrap();
+        for facet in &self.facets {
+            let [x, y, z] = facet.unit_normal();
+            writeln!(stl, "  facet normal {} {} {}", x as f32, y as f32, z as f32).unwrap();
+            writeln!(stl, "    outer loop").unwrap();
+            for vertex in &facet.vertices {
+                writeln!(stl, "      vertex {} {} {}", nanos_to_mm_string(vertex.x), nanos_to_mm_string(vertex.y), nanos_to_mm_string(vertex.z)).unwrap();
+            }
+            writeln!(stl, "    endloop").unwrap();
+            writeln!(stl, "  endfacet").unwrap();
+        }
+        writeln!(stl, "endsolid {}", name).unwrap();
+        stl
+    }
+
+    /// Moves vertices that are at most `tolerance` nanometers apart to the same position, so that facets
+    /// that are meant to touch but don't quite line up in the file share vertices exactly. Then numbers the
+    /// distinct vertices and records which facets share each edge (see [Mesh::topology]).
+    pub fn weld_vertices(&mut self, tolerance: i64) {
+        let tolerance = tolerance.max(0);
+        if tolerance > 0 {
+            // bucket vertices into a grid of cells `tolerance` big, so only the neighbouring cells need to be
+            // searched for vertices within `tolerance`
+            let cell = |vertex: &Vector3D| (vertex.x.div_euclid(tolerance), vertex.y.div_euclid(tolerance), vertex.z.div_euclid(tolerance));
+            let mut grid: HashMap<(i64, i64, i64), Vec<Vector3D>> = HashMap::new();
+            for vertex in self.facets.iter_mut().flat_map(|facet| &mut facet.vertices) {
+                let (x, y, z) = cell(vertex);
+                let existing = (x - 1..=x + 1)
+                    .flat_map(|x| (y - 1..=y + 1).flat_map(move |y| (z - 1..=z + 1).map(move |z| (x, y, z))))
+                    .filter_map(|neighbour| grid.get(&neighbour))
+                    .flatten()
+                    .find(|other| {
+                        let offset = other.sub(vertex);
+                        offset.dot(&offset) <= tolerance as i128 * tolerance as i128
+                    });
+                match existing {
+                    Some(existing) => *vertex = existing.clone(),
+                    None => grid.entry((x, y, z)).or_default().push(vertex.clone()),
+                }
+            }
+        }
+        self.topology = Some(MeshTopology::new(&self.facets));
+    }
+
+    /// The shared vertices and edges of the mesh, if [Mesh::weld_vertices] has been called
+    pub fn topology(&self) -> Option<&MeshTopology> {
+        self.topology.as_ref()
+    }
+
+    /// Whether the mesh is closed, with no holes or stray facets: every edge is shared by exactly two
+    /// facets (see [Mesh::non_manifold_edges]). Only meshes like this can be sliced into closed polygons.
+    pub fn is_manifold(&self) -> bool {
+        self.with_topology(|topology| topology.edge_facets.values().all(|facets| facets.len() == 2))
+    }
+
+    /// The edges (as the positions of their two vertices) that don't have exactly two facets. An edge with
+    /// only one facet is on the border of a hole, and one with more than two is where separate surfaces meet.
+    ///
+    /// Vertices count as shared if they are at exactly the same position, unless [Mesh::weld_vertices] has
+    /// been called with a larger tolerance.
+    pub fn non_manifold_edges(&self) -> Vec<(Vector3D, Vector3D)> {
+        self.with_topology(|topology| {
+            topology.edge_facets.iter()
+                .filter(|(_, facets)| facets.len() != 2)
+                .map(|(&(a, b), facets)| {
+                    // find the positions of the vertex numbers in the facet that has the edge
+                    let facet = facets[0];
+                    let position = |number: usize| {
+                        let corner = topology.facet_vertices[facet].iter().position(|&n| n == number).unwrap();
+                        self.facets[facet].vertices[corner].clone()
+                    };
+                    (position(a), position(b))
+                })
+                .collect()
+        })
+    }
+
+    /// Calls `f` with the mesh's topology, building it from exactly coincident vertices if
+    /// [Mesh::weld_vertices] hasn't been called
+    fn with_topology<T>(&self, f: impl FnOnce(&MeshTopology) -> T) -> T {
+        match &self.topology {
+            Some(topology) => f(topology),
+            None => f(&MeshTopology::new(&self.facets)),
+        }
     }
 
     pub fn translate(&mut self, translation: &Vector3D) {
         for facet in &mut self.facets {
-            facet.translate(&translation);
+            facet.translate(translation);
+        }
+    }
+
+    /// Rotates every vertex of the mesh `degrees` about `axis`, through the origin. Positive angles are
+    /// counterclockwise when looking down the axis towards the origin (the right-hand rule).
+    ///
+    /// Vertices are rounded to the nearest nanometer after rotating, so rotations are slightly lossy, and the
+    /// mesh ends up somewhere else if it wasn't centered on the origin. [Scene::center_on_bed] can be used
+    /// afterwards to put it back in place.
+    pub fn rotate(&mut self, axis: Axis, degrees: f64) {
+        let radians = degrees.to_radians();
+        for facet in &mut self.facets {
+            facet.rotate(axis, radians);
+        }
+    }
+
+    /// Scales the mesh by `factor` about the origin. Vertices are rounded to the nearest nanometer.
+    pub fn scale(&mut self, factor: f64) {
+        self.scale_xyz(factor, factor, factor);
+    }
+
+    /// Scales the mesh about the origin by a separate factor along each axis. Vertices are rounded to the
+    /// nearest nanometer, and normals are adjusted to stay perpendicular to their facets.
+    ///
+    /// Negative factors mirror the mesh, which turns its facets inside out.
+    pub fn scale_xyz(&mut self, x: f64, y: f64, z: f64) {
+        for facet in &mut self.facets {
+            facet.scale([x, y, z]);
+        }
+    }
+
+    /// Mirrors the mesh across the plane through the origin perpendicular to `axis`. The winding order of
+    /// every facet is reversed, so that the mesh isn't turned inside out (unlike scaling by a negative factor).
+    pub fn mirror(&mut self, axis: Axis) {
+        for facet in &mut self.facets {
+            facet.mirror(axis);
+        }
+        if let Some(topology) = &mut self.topology {
+            for vertices in &mut topology.facet_vertices {
+                vertices.swap(1, 2);
+            }
+        }
+    }
+
+    /// A copy of the mesh moved by `translation` (see [Mesh::translate])
+    pub fn translated(&self, translation: &Vector3D) -> Mesh {
+        let mut mesh = self.clone();
+        mesh.translate(translation);
+        mesh
+    }
+
+    /// A copy of the mesh rotated `degrees` about `axis` (see [Mesh::rotate])
+    pub fn rotated(&self, axis: Axis, degrees: f64) -> Mesh {
+        let mut mesh = self.clone();
+        mesh.rotate(axis, degrees);
+        mesh
+    }
+
+    /// A copy of the mesh scaled by `factor` (see [Mesh::scale])
+    pub fn scaled(&self, factor: f64) -> Mesh {
+        let mut mesh = self.clone();
+        mesh.scale(factor);
+        mesh
+    }
+
+    /// A copy of the mesh mirrored across the plane perpendicular to `axis` (see [Mesh::mirror])
+    pub fn mirrored(&self, axis: Axis) -> Mesh {
+        let mut mesh = self.clone();
+        mesh.mirror(axis);
+        mesh
+    }
+
+    /// The facets making up the mesh
+    pub fn facets(&self) -> &[Facet] {
+        &self.facets
+    }
+
+    /// Number of facets in the mesh
+    pub fn facet_count(&self) -> usize {
+        self.facets.len()
+    }
+
+    /// The smallest box containing the whole mesh, or `None` if the mesh has no facets
+    pub fn bounding_box(&self) -> Option<BoundingBox3D> {
+        BoundingBox3D::from_points(self.facets.iter().flat_map(|facet| &facet.vertices))
+    }
+
+    /// Replaces the stored normal of every facet with the one computed from its winding order.
+    /// Useful for meshes whose files contain zeroed or inconsistent normals.
+    pub fn recompute_normals(&mut self) {
+        for facet in &mut self.facets {
+            facet.normal = facet.computed_normal();
         }
     }
 }
 
+/// How the facets of a [Mesh] are connected to each other. Vertices are numbered so that facets with a
+/// vertex at the same position share that vertex's number. Created by [Mesh::weld_vertices].
+#[derive(Debug, Clone)]
+pub struct MeshTopology {
+    /// Number of distinct vertices
+    vertex_count: usize,
+    /// The vertex numbers of each facet, in the same order as the facet's vertices
+    facet_vertices: Vec<[usize; 3]>,
+    /// For each edge (as its two vertex numbers, lowest first), the indices of the facets that have it
+    edge_facets: HashMap<(usize, usize), Vec<usize>>,
+}
+
+impl MeshTopology {
+    fn new(facets: &[Facet]) -> Self {
+        let mut vertex_numbers: HashMap<&Vector3D, usize> = HashMap::new();
+        let facet_vertices: Vec<[usize; 3]> = facets.iter()
+            .map(|facet| facet.vertices.each_ref().map(|vertex| {
+                let next_number = vertex_numbers.len();
+                *vertex_numbers.entry(vertex).or_insert(next_number)
+            }))
+            .collect();
+
+        let mut edge_facets: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (index, [a, b, c]) in facet_vertices.iter().enumerate() {
+            for (start, end) in [(a, b), (b, c), (c, a)] {
+                edge_facets.entry((*start.min(end), *start.max(end))).or_default().push(index);
+            }
+        }
+
+        Self {
+            vertex_count: vertex_numbers.len(),
+            facet_vertices,
+            edge_facets,
+        }
+    }
+
+    /// Number of distinct vertices in the mesh
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_count
+    }
+
+    /// The vertex numbers of each facet of the mesh, in the same order as [Mesh]'s facets and their vertices
+    pub fn facet_vertices(&self) -> &[[usize; 3]] {
+        &self.facet_vertices
+    }
+
+    /// Maps each edge of the mesh, given by its two vertex numbers (lowest first), to the indices of the
+    /// facets that have that edge
+    pub fn edge_facets(&self) -> &HashMap<(usize, usize), Vec<usize>> {
+        &self.edge_facets
+    }
+}
+
+/// Formats a length in nanometers as exactly the same length in millimeters
+fn nanos_to_mm_string(nanos: i64) -> String {
+    let sign = if nanos < 0 { "-" } else { "" };
+    let nanos = nanos.unsigned_abs();
+    format!("{}{}.{:06}", sign, nanos / NANOS_PER_MILLIMETER as u64, nanos % NANOS_PER_MILLIMETER as u64)
+}
+
+/// Statistics about a [Scene], returned by [Scene::stats]
+#[derive(Debug, Clone)]
+pub struct SceneStats {
+    pub facet_count: usize,
+    /// See [Scene::bounding_box]
+    pub bounding_box: Option<BoundingBox3D>,
+    /// See [Scene::z_bounds]
+    pub z_bounds: Option<(i64, i64)>,
+}
+
+/// Identifies a [Mesh] in a [Scene]. Returned by [Scene::add_mesh].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(u32);
+
 /// One or more [Mesh]es that are sliced/printed together
+#[derive(Default)]
 pub struct Scene {
-    /// Every facet of every mesh
-    combined_facets: Vec<Facet>,
+    /// Ordered by id, i.e. in the order they were added
+    meshes: BTreeMap<MeshId, Mesh>,
+    next_id: u32,
 }
 
 impl Scene {
     pub fn new() -> Self {
         Self {
-            combined_facets: Vec::new(),
+            meshes: BTreeMap::new(),
+            next_id: 0,
         }
     }
 
-    /// Returns true if there are no meshes in the scene
+    /// Returns true if there are no meshes (or only meshes without any facets) in the scene
     pub fn is_empty(&self) -> bool {
-        self.combined_facets.is_empty()
+        self.facets().next().is_none()
+    }
+
+    /// Adds a mesh to the scene, returning the id to refer to it by later
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshId {
+        let id = MeshId(self.next_id);
+        self.next_id += 1;
+        self.meshes.insert(id, mesh);
+        id
     }
 
-    pub fn add_mesh(&mut self, mut mesh: Mesh) {
-        self.combined_facets.append(&mut mesh.facets)
+    /// Takes a mesh out of the scene, returning it, or `None` if it isn't in the scene
+    pub fn remove_mesh(&mut self, id: MeshId) -> Option<Mesh> {
+        self.meshes.remove(&id)
     }
 
-    pub fn to_facet_filter(self) -> FacetFilter {
-        FacetFilter::new(self.combined_facets)
+    pub fn mesh(&self, id: MeshId) -> Option<&Mesh> {
+        self.meshes.get(&id)
+    }
+
+    pub fn mesh_mut(&mut self, id: MeshId) -> Option<&mut Mesh> {
+        self.meshes.get_mut(&id)
+    }
+
+    /// Moves one mesh of the scene by `translation`. Returns false (and does nothing) if the mesh isn't in the scene.
+    pub fn transform_mesh(&mut self, id: MeshId, translation: &Vector3D) -> bool {
+        match self.meshes.get_mut(&id) {
+            Some(mesh) => {
+                mesh.translate(translation);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every facet of every mesh, in order of their [MeshId]s
+    pub fn facets(&self) -> impl Iterator<Item=&Facet> {
+        self.meshes.values().flat_map(|mesh| &mesh.facets)
+    }
+
+    /// Total number of facets in all of the meshes
+    pub fn facet_count(&self) -> usize {
+        self.meshes.values().map(Mesh::facet_count).sum()
+    }
+
+    /// A summary of the size of the scene, e.g. to show before slicing it
+    pub fn stats(&self) -> SceneStats {
+        SceneStats {
+            facet_count: self.facet_count(),
+            bounding_box: self.bounding_box(),
+            z_bounds: self.z_bounds(),
+        }
+    }
+
+    /// The smallest box containing every mesh in the scene, or `None` if the scene is empty
+    pub fn bounding_box(&self) -> Option<BoundingBox3D> {
+        BoundingBox3D::from_points(self.facets().flat_map(|facet| &facet.vertices))
+    }
+
+    /// The lowest and highest z of any facet in the scene (the heights that slicing starts and ends at),
+    /// or `None` if the scene is empty
+    pub fn z_bounds(&self) -> Option<(i64, i64)> {
+        let lowest = self.facets().map(Facet::lower_z_bound).min()?;
+        let highest = self.facets().map(Facet::upper_z_bound).max()?;
+        Some((lowest, highest))
+    }
+
+    /// Checks that the whole scene is inside a build volume of size `volume`, whose corner is at the origin (e.g.
+    /// after [Scene::center_on_bed]). Returns [Error::OutOfBounds] with the first coordinate found outside of it.
+    /// An empty scene always fits.
+    pub fn fit_check(&self, volume: Vector3D) -> Result<(), Error> {
+        let Some(bounds) = self.bounding_box() else {
+            return Ok(());
+        };
+        for (axis, min, max, size) in [
+            (Axis::X, bounds.min.x, bounds.max.x, volume.x),
+            (Axis::Y, bounds.min.y, bounds.max.y, volume.y),
+            (Axis::Z, bounds.min.z, bounds.max.z, volume.z),
+        ] {
+            if min < 0 {
+                return Err(Error::OutOfBounds { axis, position: min });
+            }
+            if max > size {
+                return Err(Error::OutOfBounds { axis, position: max });
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the whole scene is inside a build volume of size `volume` (see [Scene::fit_check])
+    pub fn fits_within(&self, volume: Vector3D) -> bool {
+        self.fit_check(volume).is_ok()
+    }
+
+    /// Moves the scene so that its XY footprint is centered on a bed of size `bed_size` (whose corner is
+    /// at the origin), and its lowest point sits at z=0. Does nothing if the scene is empty.
+    pub fn center_on_bed(&mut self, bed_size: Vector2D) {
+        let bounds = match self.bounding_box() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let translation = Vector3D::new(
+            bed_size.x / 2 - (bounds.min.x + bounds.max.x) / 2,
+            bed_size.y / 2 - (bounds.min.y + bounds.max.y) / 2,
+            -bounds.min.z,
+        );
+        for mesh in self.meshes.values_mut() {
+            mesh.translate(&translation);
+        }
+    }
+
+    /// Copies of the facets that intersect a plane at height `z` (facets whose lower bounds are below
+    /// the plane and upper bounds are above it, like [FacetFilter::intersecting_facets])
+    pub(crate) fn facets_intersecting(&self, z: i64) -> Vec<BoundedFacet> {
+        self.bounded_facets()
+            .filter(|facet| facet.lower_bound < z && facet.upper_bound > z)
+            .collect()
+    }
+
+    /// Copies of every facet of every mesh, along with the tool of the mesh
+    fn bounded_facets(&self) -> impl Iterator<Item=BoundedFacet> + '_ {
+        self.meshes.values()
+            .flat_map(|mesh| mesh.facets.iter().map(|facet| BoundedFacet::new(facet.clone(), mesh.tool)))
+    }
+
+    /// Copies the facets of all the meshes into a [FacetIndex], for slicing at many heights in any order.
+    /// Fails with [Error::EmptyScene] if the scene [is empty](Scene::is_empty).
+    pub fn to_facet_index(&self) -> Result<FacetIndex, Error> {
+        FacetIndex::new(self.bounded_facets().collect())
+    }
+
+    /// Like [Scene::to_facet_filter], but copies the facets instead of using up the scene
+    pub fn facet_filter(&self) -> Result<FacetFilter, Error> {
+        FacetFilter::new(self.bounded_facets().collect())
+    }
+
+    /// Combines the facets of all the meshes into a [FacetFilter] for slicing. Fails with
+    /// [Error::EmptyScene] if the scene [is empty](Scene::is_empty).
+    pub fn to_facet_filter(self) -> Result<FacetFilter, Error> {
+        FacetFilter::new(self.meshes.into_values()
+            .flat_map(|mesh| mesh.facets.into_iter().map(move |facet| BoundedFacet::new(facet, mesh.tool)))
+            .collect())
     }
 }
 
@@ -88,21 +682,37 @@ pub struct BoundedFacet {
     lower_bound: i64,
     /// Cached value of self.facet.upper_z_bound()
     upper_bound: i64,
+    /// [Mesh::tool] of the mesh the facet came from
+    tool: u8,
 }
 
 impl BoundedFacet {
-    fn new(facet: Facet) -> Self {
+    fn new(facet: Facet, tool: u8) -> Self {
         Self {
             lower_bound: facet.lower_z_bound(),
             upper_bound: facet.upper_z_bound(),
             facet,
+            tool,
         }
     }
 
+    /// The tool of the mesh this facet came from (see [Mesh::tool])
+    pub fn tool(&self) -> u8 {
+        self.tool
+    }
+
     pub fn vertices(&self) -> &[Vector3D; 3] {
         &self.facet.vertices
     }
 
+    /// How steep the facet is: 0.0 for a vertical facet, up to 1.0 for a horizontal one. This is the
+    /// cosine of the angle between the facet's normal and the z axis.
+    pub(crate) fn flatness(&self) -> f64 {
+        let normal = self.facet.computed_normal();
+        let length = (normal.x as f64).hypot(normal.y as f64).hypot(normal.z as f64);
+        (normal.z as f64 / length).abs()
+    }
+
     pub fn lower_z_bound(&self) -> i64 {
         self.lower_bound
     }
@@ -121,17 +731,16 @@ pub struct FacetFilter {
 }
 
 impl FacetFilter {
-    fn new(facets: Vec<Facet>) -> Self {
-        let mut facets: Vec<BoundedFacet> = facets.into_iter().map(BoundedFacet::new).collect();
+    fn new(mut facets: Vec<BoundedFacet>) -> Result<Self, Error> {
         // start height is the lowest z value of all the facets' vetexes
-        let start_height = facets.iter().map(|facet| facet.lower_bound).min().unwrap();
-        // compare b to a so it sorts in descending order
-        facets.sort_unstable_by(|a, b| b.lower_bound.cmp(&a.lower_bound));
+        let start_height = facets.iter().map(|facet| facet.lower_bound).min().ok_or(Error::EmptyScene)?;
+        // reverse the key so it sorts in descending order
+        facets.sort_unstable_by_key(|facet| std::cmp::Reverse(facet.lower_bound));
 
-        Self {
+        Ok(Self {
             facets,
             current_height: start_height,
-        }
+        })
     }
 
     /// Increases the current height by `increment` and trims facets whose upper bounds
@@ -149,8 +758,14 @@ impl FacetFilter {
     /// Returns all facets that intersect with a plane at the current height (facets whose
     /// lower bounds are below the plane and upper bounds are above the plane).
     pub fn intersecting_facets(&self) -> &[BoundedFacet] {
+        self.facets_starting_below(self.current_height)
+    }
+
+    /// Returns the remaining facets (whose upper bounds are above the current height) whose lower
+    /// bounds are below `height`
+    pub(crate) fn facets_starting_below(&self, height: i64) -> &[BoundedFacet] {
         let first_facet_not_included = self.facets.iter().enumerate().rev()
-            .find(|(_, facet)| facet.lower_bound >= self.current_height)
+            .find(|(_, facet)| facet.lower_bound >= height)
             .map(|(index, _)| index);
 
         if let Some(index) = first_facet_not_included {
@@ -169,3 +784,164 @@ impl FacetFilter {
         self.current_height
     }
 }
+
+/// Finds the facets that intersect a plane at any height, for slicing at heights in no particular order (e.g. an
+/// interactive preview). Unlike [FacetFilter], which is made for sweeping upwards through a scene one layer at a
+/// time, each query takes logarithmic time plus the time to list the facets found.
+///
+/// Created by [Scene::to_facet_index].
+pub struct FacetIndex {
+    facets: Vec<BoundedFacet>,
+    /// Nodes of an interval tree over the facets' z bounds. The root is the last node, since the nodes below
+    /// another are always built before it.
+    nodes: Vec<IntervalNode>,
+}
+
+/// A node of a [FacetIndex], holding each facet whose z bounds include `center` and that wasn't already
+/// taken by a node above it
+struct IntervalNode {
+    center: i64,
+    /// Indices of the node's facets, sorted by lower bound in ascending order
+    by_lower_bound: Vec<usize>,
+    /// Indices of the node's facets, sorted by upper bound in descending order
+    by_upper_bound: Vec<usize>,
+    /// Node of the facets entirely below `center`
+    below: Option<usize>,
+    /// Node of the facets entirely above `center`
+    above: Option<usize>,
+}
+
+impl FacetIndex {
+    fn new(facets: Vec<BoundedFacet>) -> Result<Self, Error> {
+        if facets.is_empty() {
+            return Err(Error::EmptyScene);
+        }
+        // horizontal facets never intersect a plane (see FacetFilter::intersecting_facets)
+        let indices = (0..facets.len()).filter(|&index| facets[index].lower_bound < facets[index].upper_bound).collect();
+        let mut nodes = Vec::new();
+        Self::build(&facets, indices, &mut nodes);
+        Ok(Self { facets, nodes })
+    }
+
+    /// Adds the nodes for the facets at `indices` to `nodes`, returning the index of the topmost one
+    fn build(facets: &[BoundedFacet], mut indices: Vec<usize>, nodes: &mut Vec<IntervalNode>) -> Option<usize> {
+        if indices.is_empty() {
+            return None;
+        }
+        // centering the node on the median facet's midpoint leaves at most half of the facets on either side
+        let midpoint = |index: usize| (facets[index].lower_bound + facets[index].upper_bound) / 2;
+        let median = indices.len() / 2;
+        let center = midpoint(*indices.select_nth_unstable_by_key(median, |&index| midpoint(index)).1);
+
+        let (mut below, mut above, mut here) = (Vec::new(), Vec::new(), Vec::new());
+        for index in indices {
+            if facets[index].upper_bound < center {
+                below.push(index);
+            } else if facets[index].lower_bound > center {
+                above.push(index);
+            } else {
+                here.push(index);
+            }
+        }
+        let below = Self::build(facets, below, nodes);
+        let above = Self::build(facets, above, nodes);
+
+        let mut by_lower_bound = here.clone();
+        by_lower_bound.sort_unstable_by_key(|&index| facets[index].lower_bound);
+        let mut by_upper_bound = here;
+        by_upper_bound.sort_unstable_by_key(|&index| std::cmp::Reverse(facets[index].upper_bound));
+        nodes.push(IntervalNode { center, by_lower_bound, by_upper_bound, below, above });
+        Some(nodes.len() - 1)
+    }
+
+    /// Returns all facets that intersect with a plane at height `z` (facets whose lower bounds are below the
+    /// plane and upper bounds are above it, like [FacetFilter::intersecting_facets])
+    pub fn intersecting_facets(&self, z: i64) -> Vec<&BoundedFacet> {
+        let mut found = Vec::new();
+        let mut next = self.nodes.len().checked_sub(1);
+        while let Some(node) = next.map(|index| &self.nodes[index]) {
+            // every facet of the node includes the center, so only the bound on the far side of `z` from
+            // the center needs checking
+            if z <= node.center {
+                found.extend(node.by_lower_bound.iter()
+                    .map(|&index| &self.facets[index])
+                    .take_while(|facet| facet.lower_bound < z)
+                    .filter(|facet| facet.upper_bound > z));
+            } else {
+                found.extend(node.by_upper_bound.iter()
+                    .map(|&index| &self.facets[index])
+                    .take_while(|facet| facet.upper_bound > z));
+            }
+            next = match z.cmp(&node.center) {
+                std::cmp::Ordering::Less => node.below,
+                std::cmp::Ordering::Greater => node.above,
+                std::cmp::Ordering::Equal => None,
+            };
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{parse_mesh_file, FileFormat, MeshFileUnits};
+
+    /// A tetrahedron with 10mm edges along the axes, its corner at `corner`, with normals facing out
+    fn tetrahedron(corner: Vector3D) -> Mesh {
+        let size = 10_000_000;
+        let vertices = [
+            Vector3D::new(0, 0, 0),
+            Vector3D::new(size, 0, 0),
+            Vector3D::new(0, size, 0),
+            Vector3D::new(0, 0, size),
+        ].map(|mut vertex| { vertex.add(&corner); vertex });
+        Mesh::from_indexed(&vertices, &[[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]]).unwrap()
+    }
+
+    #[test]
+    fn binary_stl_round_trips() {
+        let mesh = tetrahedron(Vector3D::new(1_500_000, -2_250_000, 3_000_000));
+        let parsed = parse_mesh_file(&mesh.to_binary_stl(), FileFormat::BinaryStl, MeshFileUnits::Millimeters).unwrap();
+        assert_eq!(parsed.facets.len(), mesh.facets.len());
+        for (written, read) in mesh.facets.iter().zip(&parsed.facets) {
+            assert_eq!(read.vertices, written.vertices);
+            assert!(read.normal().dot(written.normal()) > 0);
+        }
+    }
+
+    #[test]
+    fn mirroring_keeps_normals_facing_out() {
+        for axis in [Axis::X, Axis::Y, Axis::Z] {
+            let mut mesh = tetrahedron(Vector3D::new(1_000_000, 2_000_000, 3_000_000));
+            mesh.mirror(axis);
+            let points: Vec<&Vector3D> = mesh.facets.iter().flat_map(|facet| &facet.vertices).collect();
+            let mean = |coordinate: fn(&Vector3D) -> i64| points.iter().map(|&point| coordinate(point) as f64).sum::<f64>() / points.len() as f64;
+            let center = [mean(|point| point.x), mean(|point| point.y), mean(|point| point.z)];
+            for facet in &mesh.facets {
+                let computed = facet.computed_normal();
+                assert!(computed.dot(facet.normal()) > 0, "{:?}: stored normal disagrees with the winding", axis);
+                // the normal points from the middle of the mesh towards the facet
+                let [a, b, c] = &facet.vertices;
+                let outwards = [
+                    (a.x + b.x + c.x) as f64 / 3.0 - center[0],
+                    (a.y + b.y + c.y) as f64 / 3.0 - center[1],
+                    (a.z + b.z + c.z) as f64 / 3.0 - center[2],
+                ];
+                let alignment = computed.x as f64 * outwards[0] + computed.y as f64 * outwards[1] + computed.z as f64 * outwards[2];
+                assert!(alignment > 0.0, "{:?}: facet is wound inside out", axis);
+            }
+        }
+    }
+
+    #[test]
+    fn computed_normal_of_huge_facets() {
+        for size in [5_000_000_000, 1 << 61] {
+            let facet = Facet::new([Vector3D::new(0, 0, 0), Vector3D::new(size, 0, 0), Vector3D::new(0, size, size)]);
+            let normal = facet.normal();
+            assert_eq!(normal.x, 0);
+            assert!(normal.y < 0 && normal.z > 0);
+            assert_eq!(normal.y, -normal.z);
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use std::convert::TryInto;
-use crate::geometry::Vector3D;
+use crate::geometry::{Vector3D, NANOS_PER_MILLIMETER};
 use crate::mesh::{Facet, Mesh};
 use crate::Error;
 
@@ -18,17 +18,61 @@ pub enum FileFormat {
 pub enum MeshFileUnits {
     Inches,
     Millimeters,
+    /// Guess the units from the size of the model, since STL files don't say what units they use. The file is
+    /// parsed in millimeters, and if the model is smaller than `inch_threshold` (in nanometers) along every axis,
+    /// which would be implausibly tiny to print, it is parsed again in inches. [MeshFileUnits::AUTO] uses
+    /// a threshold of 3mm.
+    Auto {
+        inch_threshold: u64,
+    },
 }
 
-const NANOS_PER_INCH: f32 = 25400000.0;
-const NANOS_PER_MILLIMETER: f32 = 1000000.0;
+impl MeshFileUnits {
+    /// [MeshFileUnits::Auto] with the default threshold: models under 3mm in every axis are in inches
+    pub const AUTO: Self = Self::Auto { inch_threshold: 3_000_000 };
+}
+
+const NANOS_PER_INCH: f64 = 25400000.0;
 
 /// Parses a `Mesh` from the file whose contents are given by `bytes`. `units` is what measurement unit the file uses.
 /// All measurements are converted to integer nanometers, which is what the rest of the library uses.
 pub fn parse_mesh_file(bytes: &[u8], format: FileFormat, units: MeshFileUnits) -> Result<Mesh, Error> {
-    match format {
+    let parse = |units| match format {
         FileFormat::AsciiStl => AsciiStlParser::new(bytes, units).parse(),
         FileFormat::BinaryStl => BinaryStlParser::new(bytes, units).parse(),
+    };
+    let mesh = parse(units)?;
+    if let MeshFileUnits::Auto { inch_threshold } = units {
+        let tiny = mesh.bounding_box().is_some_and(|bounds| {
+            let size = bounds.max.sub(&bounds.min);
+            [size.x, size.y, size.z].iter().all(|&length| length < inch_threshold as i64)
+        });
+        if tiny {
+            // parse again instead of scaling up, so that coordinates aren't rounded twice
+            return parse(MeshFileUnits::Inches);
+        }
+    }
+    Ok(mesh)
+}
+
+/// Like [parse_mesh_file], but detects the STL type itself (see [detect_stl_type]), and first decompresses
+/// `bytes` if they are gzipped (like a `.stl.gz` file). Bytes without the gzip magic number are parsed
+/// as they are.
+///
+/// Returns [Error::MeshFileParse] if the gzip data is corrupt.
+#[cfg(feature = "flate2")]
+pub fn parse_mesh_file_maybe_gzip(bytes: &[u8], units: MeshFileUnits) -> Result<Mesh, Error> {
+    use std::io::Read;
+
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_end(&mut decompressed)
+            .map_err(|_| Error::MeshFileParse)?;
+        parse_mesh_file(&decompressed, detect_stl_type(&decompressed), units)
+    } else {
+        parse_mesh_file(bytes, detect_stl_type(bytes), units)
     }
 }
 
@@ -48,14 +92,15 @@ pub fn detect_stl_type(bytes: &[u8]) -> FileFormat {
 }
 
 /// Returns true if `coordinate` is finite and non-NaN.
-fn is_valid_coordinate(coordinate: f32) -> bool {
+fn is_valid_coordinate(coordinate: f64) -> bool {
     !matches!(coordinate.classify(), std::num::FpCategory::Infinite | std::num::FpCategory::Nan)
 }
 
-fn convert_to_nanos(value: f32, units: MeshFileUnits) -> f32 {
+fn convert_to_nanos(value: f64, units: MeshFileUnits) -> f64 {
     match units {
         MeshFileUnits::Inches => value * NANOS_PER_INCH,
-        MeshFileUnits::Millimeters => value * NANOS_PER_MILLIMETER,
+        // auto-detection starts by assuming millimeters (see parse_mesh_file)
+        MeshFileUnits::Millimeters | MeshFileUnits::Auto { .. } => value * NANOS_PER_MILLIMETER,
     }
 }
 
@@ -88,12 +133,19 @@ impl<'a> BinaryStlParser<'a> {
         self.facets.reserve(facet_count as usize);
         for _ in 0..facet_count {
             let facet = self.parse_facet()?;
-            self.facets.push(facet);
-            // Attributes aren't used in vanilla STL files - we ignore this field
-            let _attribute_byte_count = self.parse_u16()?;
+            // vanilla STL files set this to 0, but some programs store a color in it (see Facet::color)
+            let facet = facet.with_attribute(self.parse_u16()?);
+            // zero-area facets don't contribute anything to the mesh, but would break slicing
+            if !facet.is_degenerate() {
+                self.facets.push(facet);
+            }
         }
 
-        Ok(Mesh::new(self.facets))
+        if self.facets.is_empty() {
+            Err(Error::MeshFileParse)
+        } else {
+            Ok(Mesh::new(self.facets))
+        }
     }
 
     /// How many bytes are left in the buffer
@@ -141,7 +193,7 @@ impl<'a> BinaryStlParser<'a> {
     }
 
     /// Parse the next f32 from the buffer, and convert it into nanometers. Errors if the float is NaN or infinite.
-    fn parse_unitized_f32(&mut self) -> Result<f32, Error> {
+    fn parse_unitized_f32(&mut self) -> Result<f64, Error> {
         const NUM_BYTES: usize = std::mem::size_of::<f32>();
         if self.bytes_remaining() < NUM_BYTES {
             return Err(Error::MeshFileParse);
@@ -151,7 +203,7 @@ impl<'a> BinaryStlParser<'a> {
             .map_err(|_| Error::MeshFileParse)?;
         self.index += NUM_BYTES;
 
-        let float = convert_to_nanos(f32::from_le_bytes(bytes), self.units);
+        let float = convert_to_nanos(f32::from_le_bytes(bytes) as f64, self.units);
 
         if is_valid_coordinate(float) {
             Ok(float)
@@ -162,13 +214,17 @@ impl<'a> BinaryStlParser<'a> {
 
     /// Parse the next `Vector3D` from the buffer
     fn parse_point(&mut self) -> Result<Vector3D, Error> {
-        Ok(Vector3D::new(self.parse_unitized_f32()? as i64, self.parse_unitized_f32()? as i64, self.parse_unitized_f32()? as i64))
+        Ok(Vector3D::new(
+            self.parse_unitized_f32()?.round() as i64,
+            self.parse_unitized_f32()?.round() as i64,
+            self.parse_unitized_f32()?.round() as i64,
+        ))
     }
 
     /// Parse the next `Facet` from the buffer
     fn parse_facet(&mut self) -> Result<Facet, Error> {
-        let _normal = self.parse_point()?;
-        Ok(Facet::new([self.parse_point()?, self.parse_point()?, self.parse_point()?]))
+        let normal = self.parse_point()?;
+        Ok(Facet::with_normal([self.parse_point()?, self.parse_point()?, self.parse_point()?], normal))
     }
 }
 
@@ -193,10 +249,10 @@ impl<'a> AsciiStlParser<'a> {
         self.eat_line_space()?;
 
         loop {
-            self.eat_string(b"facet normal")?;
+            self.eat_words(&[b"facet", b"normal"])?;
             self.eat_whitespace();
-            let _normal = self.parse_point()?;
-            self.eat_string(b"outer loop")?;
+            let normal = self.parse_point()?;
+            self.eat_words(&[b"outer", b"loop"])?;
             self.eat_line_space()?;
             let mut points = Vec::with_capacity(3);
             for _ in 0..3 {
@@ -206,7 +262,11 @@ impl<'a> AsciiStlParser<'a> {
             }
             // this unwrap is safe because we know the Vec has 3 elements
             let points: [Vector3D; 3] = points.try_into().unwrap();
-            self.facets.push(Facet::new(points));
+            let facet = Facet::with_normal(points, normal);
+            // zero-area facets don't contribute anything to the mesh, but would break slicing
+            if !facet.is_degenerate() {
+                self.facets.push(facet);
+            }
             self.eat_string(b"endloop")?;
             self.eat_line_space()?;
             self.eat_string(b"endfacet")?;
@@ -233,12 +293,36 @@ impl<'a> AsciiStlParser<'a> {
         }
     }
 
-    /// Eats chars until a newline (eats the newline too).
-    fn eat_line(&mut self) -> Result<(), Error> {
-        while self.eat_char()? != b'\n' {}
+    /// Eats `words`, which are separated by any amount of whitespace. Returns `Err` if they don't match.
+    fn eat_words(&mut self, words: &[&[u8]]) -> Result<(), Error> {
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                if !self.chars.first().is_some_and(u8::is_ascii_whitespace) {
+                    return Err(Error::MeshFileParse);
+                }
+                self.eat_whitespace();
+            }
+            self.eat_string(word)?;
+        }
         Ok(())
     }
 
+    /// Eats chars until a line ending (eats the line ending too). Lines can end with `\n`, `\r\n`, or `\r`.
+    fn eat_line(&mut self) -> Result<(), Error> {
+        loop {
+            match self.eat_char()? {
+                b'\n' => return Ok(()),
+                b'\r' => {
+                    if self.chars.first() == Some(&b'\n') {
+                        self.chars = &self.chars[1..];
+                    }
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
     /// Eats one char.
     fn eat_char(&mut self) -> Result<u8, Error> {
         if !self.chars.is_empty() {
@@ -272,8 +356,8 @@ impl<'a> AsciiStlParser<'a> {
     }
 
     fn parse_point(&mut self) -> Result<Vector3D, Error> {
-        let mut coordinates: [f32; 3] = [0.0; 3];
-        for i in 0..3 {
+        let mut coordinates: [f64; 3] = [0.0; 3];
+        for coordinate in &mut coordinates {
             let mut float = String::new();
             while !self.chars.is_empty() && !self.chars[0].is_ascii_whitespace() {
                 // this unwrap is safe because we already made sure that `chars` isn't empty
@@ -283,10 +367,47 @@ impl<'a> AsciiStlParser<'a> {
             if !is_valid_coordinate(coord) {
                 return Err(Error::MeshFileParse);
             }
-            coordinates[i] = coord;
+            *coordinate = coord;
             self.eat_whitespace();
         }
 
-        Ok(Vector3D::new(coordinates[0] as i64, coordinates[1] as i64, coordinates[2] as i64))
+        Ok(Vector3D::new(coordinates[0].round() as i64, coordinates[1].round() as i64, coordinates[2].round() as i64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meter_scale_facets() {
+        let stl = b"solid big
+facet normal 0 0 1
+  outer loop
+    vertex 0 0 0
+    vertex 5000 0 0
+    vertex 0 5000 0
+  endloop
+endfacet
+facet normal 0 0 0
+  outer loop
+    vertex 0 0 0
+    vertex 5000 5000 5000
+    vertex 2500 2500 2500
+  endloop
+endfacet
+facet normal 0 0 0
+  outer loop
+    vertex 0 0 0
+    vertex 5000 5000 5000
+    vertex 2500 2500 2500.001
+  endloop
+endfacet
+endsolid big
+";
+        let mesh = parse_mesh_file(stl, FileFormat::AsciiStl, MeshFileUnits::Millimeters).unwrap();
+        // only the facet whose vertices are all on one line is dropped, not the sliver next to it
+        let vertices: Vec<_> = mesh.facets().iter().map(|facet| facet.vertices()[2].clone()).collect();
+        assert_eq!(vertices, [Vector3D::new(0, 5_000_000_000, 0), Vector3D::new(2_500_000_000, 2_500_000_000, 2_500_001_000)]);
     }
 }
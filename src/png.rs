@@ -0,0 +1,66 @@
+/// Encodes an 8-bit grayscale image as a PNG file. `pixels` holds `width * height` bytes, row by row from the top.
+///
+/// The image data is stored without compression, so that this doesn't need a deflate implementation.
+pub(crate) fn encode_grayscale(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+    /// Largest amount of data in a single uncompressed deflate block
+    const MAX_BLOCK: usize = 0xffff;
+
+    // each row starts with its filter type, 0 for none
+    let mut scanlines = Vec::with_capacity((width + 1) * height);
+    for row in pixels.chunks(width.max(1)).take(height) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    // a zlib stream (deflate with no compression, no preset dictionary) of the scanlines
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if scanlines.is_empty() { vec![&[]] } else { scanlines.chunks(MAX_BLOCK).collect() };
+    for (index, block) in blocks.iter().enumerate() {
+        let is_last = index == blocks.len() - 1;
+        zlib.push(is_last as u8);
+        zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&scanlines).to_be_bytes());
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per pixel, grayscale, deflate compression, adaptive filtering, no interlacing
+    header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    for (kind, data) in [(b"IHDR", &header), (b"IDAT", &zlib), (b"IEND", &Vec::new())] {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    png
+}
+
+/// The CRC-32 checksum of `bytes`, as used by PNG chunks
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// The Adler-32 checksum of `bytes`, as used by zlib streams
+fn adler32(bytes: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1, 0);
+    for &byte in bytes {
+        a = (a + byte as u32) % MODULUS;
+        b = (b + a) % MODULUS;
+    }
+    (b << 16) | a
+}
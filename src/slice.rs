@@ -1,41 +1,282 @@
-use crate::geometry::{Polygon, Vector3D, Vector2D};
-use crate::mesh::{Scene, BoundedFacet};
-use crate::{Error, ConfigProfile};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::geometry::{self, Polygon, Vector3D, Vector2D};
+use crate::geometry::boolean::{self, Region};
+use crate::mesh::{Scene, BoundedFacet, FacetFilter, FacetIndex};
+use crate::{Error, ConfigProfile, AdaptiveLayerHeight, Warning};
+use crate::infill;
 
 /// A single closed polygon in a slice. One slice can contain multiple closed polygons that aren't connected.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct SliceIsland {
     outline: Polygon,
     /// Negative spaces inside the `outline`
     holes: Vec<Polygon>,
+    /// Loops printed along the walls of the island. `perimeters[0]` holds the outermost loops,
+    /// each following index is one extrusion width further in.
+    perimeters: Vec<Vec<Polygon>>,
+    /// Loops printed outside the outline on the first layer, to help it stick to the bed. `brim[0]` is
+    /// the outermost loop.
+    brim: Vec<Polygon>,
+    /// Straight lines sparsely filling the area inside the perimeters
+    infill: Vec<[Vector2D; 2]>,
+    /// Straight lines completely filling the parts of the area inside the perimeters that are near
+    /// the top or bottom of the model
+    solid_infill: Vec<[Vector2D; 2]>,
+    /// Straight lines completely filling the parts of the area inside the perimeters that have nothing
+    /// under them in the slice below, all going in whichever direction makes them shortest
+    bridge_infill: Vec<[Vector2D; 2]>,
+    /// Closely spaced lines covering the parts of the area inside the perimeters that are top surfaces
+    /// (with nothing in the slice above), to run the nozzle over again after printing them
+    ironing: Vec<[Vector2D; 2]>,
+    /// Which extruder prints this island
+    tool: u8,
 }
 
 impl SliceIsland {
+    fn new(outline: Polygon, holes: Vec<Polygon>) -> Self {
+        Self {
+            outline,
+            holes,
+            perimeters: Vec::new(),
+            brim: Vec::new(),
+            infill: Vec::new(),
+            solid_infill: Vec::new(),
+            bridge_infill: Vec::new(),
+            ironing: Vec::new(),
+            tool: 0,
+        }
+    }
+
+    /// Simplifies the outline and holes (see [Polygon::simplify])
+    fn simplify(&mut self, tolerance: i64) {
+        self.outline = self.outline.simplify(tolerance);
+        for hole in &mut self.holes {
+            *hole = hole.simplify(tolerance);
+        }
+    }
+
+    /// The outside edge of this island, going counterclockwise
     pub fn outline(&self) -> &Polygon {
         &self.outline
     }
 
+    /// The edges of the holes inside the outline, each going clockwise
     pub fn holes(&self) -> &[Polygon] {
         &self.holes
     }
+
+    /// The perimeter loops of this island, grouped by how far in they are (outermost first).
+    /// Each group contains the loop along the outline and the loops around holes.
+    pub fn perimeters(&self) -> &[Vec<Polygon>] {
+        &self.perimeters
+    }
+
+    /// The brim loops of this island (see [ConfigProfile::brim_loops]), outermost first. Only islands
+    /// on the first slice have a brim.
+    pub fn brim(&self) -> &[Polygon] {
+        &self.brim
+    }
+
+    /// The sparse infill lines of this island, in printing order
+    pub fn infill(&self) -> &[[Vector2D; 2]] {
+        &self.infill
+    }
+
+    /// The solid infill lines of this island (see [ConfigProfile::top_layers]), in printing order
+    pub fn solid_infill(&self) -> &[[Vector2D; 2]] {
+        &self.solid_infill
+    }
+
+    /// The bridge infill lines of this island, which span across open space over the slice below, in
+    /// printing order
+    pub fn bridge_infill(&self) -> &[[Vector2D; 2]] {
+        &self.bridge_infill
+    }
+
+    /// The number of the extruder that prints this island: the [Mesh::tool](crate::mesh::Mesh::tool) of the
+    /// mesh it was sliced from, or 0 for islands that aren't part of a mesh, like the raft
+    pub fn tool(&self) -> u8 {
+        self.tool
+    }
+
+    /// The ironing lines of this island (see [ConfigProfile::ironing]), in printing order. Empty unless
+    /// ironing is enabled.
+    pub fn ironing(&self) -> &[[Vector2D; 2]] {
+        &self.ironing
+    }
+
+    /// The spans of a scanline (see [infill::scanline_spans]) that are inside this island
+    fn scanline_spans(&self, position: i64, vertical: bool) -> Vec<(i64, i64)> {
+        let outline = infill::scanline_spans(std::slice::from_ref(&self.outline), position, vertical);
+        infill::subtract_spans(&outline, &infill::scanline_spans(&self.holes, position, vertical))
+    }
+}
+
+impl From<Region> for SliceIsland {
+    fn from(region: Region) -> Self {
+        Self::new(region.outline, region.holes)
+    }
 }
 
 /// A single layer of a sliced mesh. Composed of multiple `SliceIsland`s.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Slice {
     /// The thickness (in nanometers) of this slice (the "layer height")
     thickness: u64,
+    /// Empty space (in nanometers) between the top of the slice below and the bottom of this one
+    air_gap: u64,
     islands: Vec<SliceIsland>,
+    /// Loops printed around all of the islands before them, to prime the nozzle. Only the first slice
+    /// has a skirt.
+    skirt: Vec<Polygon>,
 }
 
 impl Slice {
+    /// Thickness of the slice in nanometers, like [ConfigProfile::layer_height]
     pub fn thickness(&self) -> u64 {
         self.thickness
     }
 
+    /// Space (in nanometers) left empty below this slice, which is only nonzero for the first slice of the model
+    /// above a raft (see [ConfigProfile::raft_air_gap]), and for slices above empty layers that were left out
+    /// (see [ConfigProfile::skip_empty_layers])
+    pub fn air_gap(&self) -> u64 {
+        self.air_gap
+    }
+
+    /// The separate areas of this slice, each with its own outline
     pub fn islands(&self) -> &[SliceIsland] {
         &self.islands
     }
+
+    /// Whether nothing is printed in this slice, because it is in a gap in the model. Empty slices are only
+    /// kept if [ConfigProfile::skip_empty_layers] is off.
+    pub fn is_empty(&self) -> bool {
+        self.islands.is_empty()
+    }
+
+    /// Draws the islands of this slice as an SVG image, for debugging. Each island is a filled path whose holes
+    /// are left unfilled. Coordinates are in millimeters, with y flipped so that the image shows the slice
+    /// from above, and the image is just big enough to fit the slice.
+    pub fn to_svg(&self) -> String {
+        use std::fmt::Write;
+
+        let mm = |nanos: i64| nanos as f64 / geometry::NANOS_PER_MILLIMETER;
+        let vertices = self.islands.iter().flat_map(|island| island.outline.vertices());
+        let bounds = (
+            vertices.clone().map(|v| v.x).min(),
+            vertices.clone().map(|v| v.y).min(),
+            vertices.clone().map(|v| v.x).max(),
+            vertices.map(|v| v.y).max(),
+        );
+        let view_box = match bounds {
+            (Some(min_x), Some(min_y), Some(max_x), Some(max_y)) => {
+                format!("{} {} {} {}", mm(min_x), mm(-max_y), mm(max_x - min_x), mm(max_y - min_y))
+            }
+            _ => "0 0 0 0".to_string(),
+        };
+
+        let mut svg = String::new();
+        // writing to a String can't fail
+        writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{}">"#, view_box).unwrap();
+        for island in &self.islands {
+            let mut path = String::new();
+            for polygon in std::iter::once(&island.outline).chain(&island.holes) {
+                for (i, vertex) in polygon.vertices().iter().enumerate() {
+                    let command = if i == 0 { 'M' } else { 'L' };
+                    write!(path, "{}{} {} ", command, mm(vertex.x), mm(-vertex.y)).unwrap();
+                }
+                path.push_str("Z ");
+            }
+            writeln!(svg, r#"  <path fill-rule="evenodd" d="{}"/>"#, path.trim_end()).unwrap();
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// Rasterizes the islands of this slice, for debugging and comparing slices pixel by pixel. Pixels whose
+    /// centers are inside an island (and not inside one of its holes) are filled. Like [Slice::to_svg], the
+    /// image shows the slice from above and is just big enough to fit it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `px_per_mm` isn't a positive number.
+    pub fn to_bitmap(&self, px_per_mm: f32) -> SliceBitmap {
+        assert!(px_per_mm > 0.0, "px_per_mm must be positive");
+        let pixel_size = (geometry::NANOS_PER_MILLIMETER / px_per_mm as f64).max(1.0);
+        let vertices = self.islands.iter().flat_map(|island| island.outline.vertices());
+        let (Some(min_x), Some(max_x), Some(max_y), Some(min_y)) = (
+            vertices.clone().map(|v| v.x).min(),
+            vertices.clone().map(|v| v.x).max(),
+            vertices.clone().map(|v| v.y).max(),
+            vertices.map(|v| v.y).min(),
+        ) else {
+            return SliceBitmap { width: 0, height: 0, pixels: Vec::new() };
+        };
+        let width = ((max_x - min_x) as f64 / pixel_size).ceil() as usize;
+        let height = ((max_y - min_y) as f64 / pixel_size).ceil() as usize;
+
+        let mut pixels = vec![0; width * height];
+        for (row, row_pixels) in pixels.chunks_mut(width.max(1)).enumerate() {
+            // the top row is at the highest y
+            let y = max_y - ((row as f64 + 0.5) * pixel_size).round() as i64;
+            for (start, end) in self.scanline_spans(y, false) {
+                // the columns whose centers are in the span
+                let first = (((start - min_x) as f64 / pixel_size) - 0.5).ceil().max(0.0) as usize;
+                let last = (((end - min_x) as f64 / pixel_size) - 0.5).ceil().max(0.0) as usize;
+                row_pixels[first.min(width)..last.min(width)].fill(u8::MAX);
+            }
+        }
+        SliceBitmap { width, height, pixels }
+    }
+
+    /// The skirt loops of this slice (see [ConfigProfile::skirt_loops]), innermost first
+    pub fn skirt(&self) -> &[Polygon] {
+        &self.skirt
+    }
+
+    /// The spans of a scanline (see [infill::scanline_spans]) that are inside any of the islands
+    fn scanline_spans(&self, position: i64, vertical: bool) -> Vec<(i64, i64)> {
+        // islands don't overlap, so their spans don't either
+        let mut spans: Vec<(i64, i64)> = self.islands.iter()
+            .flat_map(|island| island.scanline_spans(position, vertical))
+            .collect();
+        spans.sort_unstable();
+        spans
+    }
+
+    /// Whether `point` is inside any of the islands (and not inside their holes)
+    fn contains(&self, point: &Vector2D) -> bool {
+        self.islands.iter()
+            .any(|island| island.outline.contains(point) && !island.holes.iter().any(|hole| hole.contains(point)))
+    }
+}
+
+/// A grayscale image of a slice, made by [Slice::to_bitmap]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SliceBitmap {
+    pub width: usize,
+    pub height: usize,
+    /// `width * height` pixels, row by row starting from the top, each 255 if it is filled and 0 if not
+    pub pixels: Vec<u8>,
+}
+
+impl SliceBitmap {
+    /// Encodes the image as a PNG file
+    pub fn to_png(&self) -> Vec<u8> {
+        crate::png::encode_grayscale(self.width, self.height, &self.pixels)
+    }
+}
+
+/// Serializes `slices` to JSON, with the same structure as the [Slice] and [SliceIsland] structs.
+/// All coordinates and slice thicknesses are integer nanometers.
+#[cfg(feature = "serde")]
+pub fn slices_to_json(slices: &[Slice]) -> String {
+    // the slices only contain numbers, vecs and structs, which can always be serialized
+    serde_json::to_string(slices).unwrap()
 }
 
 /// Returns a 2D point which is the result of interpolating `a` along the line segment a---b so that
@@ -45,7 +286,7 @@ fn zinterpolate(a_in: &Vector3D, b_in: &Vector3D, plane_z: i64) -> Option<Vector
     // interpolating a to b can give a different result than b to a, so make sure that whatever order
     // a and b are passed in, they get interpolated in the same way
     let (a, b) = {
-        if a_in.pseudo_lt(&b_in) {
+        if a_in.pseudo_lt(b_in) {
             (a_in, b_in)
         } else {
             (b_in, a_in)
@@ -78,52 +319,100 @@ fn zinterpolate(a_in: &Vector3D, b_in: &Vector3D, plane_z: i64) -> Option<Vector
     }
 }
 
-/// Extracts one group of connected segments from `segments` and stitches them into a polygon.
-fn stitch_next(segments: &mut Vec<[Vector2D; 2]>) -> Option<Result<Polygon, Error>> {
-    if segments.is_empty() {
-        return None;
+/// Stitches slice segments into closed polygons, using a hash map from each endpoint to the segments
+/// that end there to find connected segments.
+struct SegmentStitcher {
+    segments: Vec<[Vector2D; 2]>,
+    /// Indices of the segments that haven't been stitched yet. They are kept in the same order as
+    /// if they were removed from a `Vec` with `swap_remove`, which decides which segment each
+    /// polygon starts from.
+    remaining: Vec<usize>,
+    /// Where each remaining segment is in `remaining`
+    positions: Vec<usize>,
+    /// The segments touching each endpoint. Segments are left in here after being stitched.
+    endpoints: HashMap<Vector2D, Vec<usize>>,
+    stitched: Vec<bool>,
+    /// Height of the slicing plane that the segments are from, for errors
+    plane: i64,
+}
+
+impl SegmentStitcher {
+    fn new(segments: Vec<[Vector2D; 2]>, plane: i64) -> Self {
+        let mut endpoints: HashMap<Vector2D, Vec<usize>> = HashMap::with_capacity(segments.len());
+        for (index, [a, b]) in segments.iter().enumerate() {
+            endpoints.entry(a.clone()).or_default().push(index);
+            endpoints.entry(b.clone()).or_default().push(index);
+        }
+        Self {
+            remaining: (0..segments.len()).collect(),
+            positions: (0..segments.len()).collect(),
+            stitched: vec![false; segments.len()],
+            segments,
+            endpoints,
+            plane,
+        }
     }
 
-    if segments.len() < 3 {
-        return Some(Err(Error::OpenStitchPolygon));
+    /// Marks a segment as stitched, and removes it from `remaining`
+    fn remove(&mut self, segment: usize) {
+        let position = self.positions[segment];
+        self.remaining.swap_remove(position);
+        if let Some(&moved) = self.remaining.get(position) {
+            self.positions[moved] = position;
+        }
+        self.stitched[segment] = true;
     }
 
-    let [first_segment_a, first_segment_b] = segments.pop().unwrap();
-    let mut builder = Polygon::builder(first_segment_a);
+    /// Finds an unstitched segment touching `point`
+    fn segment_at(&self, point: &Vector2D) -> Option<usize> {
+        // if several segments touch the point, take the one that comes first in `remaining`
+        self.endpoints.get(point)?.iter()
+            .filter(|&&segment| !self.stitched[segment])
+            .min_by_key(|&&segment| self.positions[segment])
+            .copied()
+    }
 
-    let mut open_end = first_segment_b;
-    loop {
-        let mut next_vert_idx: Option<(usize, usize)> = None;
-        for (index, segment) in segments.iter().enumerate() {
-            if segment[0] == open_end {
-                next_vert_idx = Some((index, 1));
-                break;
-            } else if segment[1] == open_end {
-                next_vert_idx = Some((index, 0));
-                break;
-            }
-        }
+    /// Extracts one group of connected segments and stitches them into a polygon. Groups that close up with
+    /// fewer than three segments enclose no area, so they are skipped.
+    fn next_polygon(&mut self) -> Option<Result<Polygon, Error>> {
+        loop {
+            let &first_segment = self.remaining.last()?;
+            self.remove(first_segment);
+            let [first_segment_a, first_segment_b] = self.segments[first_segment].clone();
+            let mut builder = Polygon::builder(first_segment_a);
 
-        if next_vert_idx.is_none() && open_end != *builder.get_start() {
-            return Some(Err(Error::OpenStitchPolygon));
-        }
+            let mut open_end = first_segment_b;
+            let mut chain_length = 1;
+            loop {
+                let next_segment = self.segment_at(&open_end);
 
-        if let Some((index, vertindex)) = next_vert_idx {
-            let segment = segments.swap_remove(index);
-            builder.line_to(open_end);
-            let [v0, v1] = segment;
-            if vertindex == 0 {
-                open_end = v0;
-            } else {
-                open_end = v1;
+                if next_segment.is_none() && open_end != *builder.get_start() {
+                    return Some(Err(Error::OpenStitchPolygon {
+                        z: self.plane,
+                        ends: [builder.get_start().clone(), open_end],
+                        segments: chain_length,
+                    }));
+                }
+
+                if let Some(segment) = next_segment {
+                    self.remove(segment);
+                    chain_length += 1;
+                    let [v0, v1] = &self.segments[segment];
+                    let next_end = if *v0 == open_end { v1.clone() } else { v0.clone() };
+                    builder.line_to(open_end);
+                    open_end = next_end;
+                } else if chain_length < 3 {
+                    // a point, or a segment doubling back on itself
+                    break;
+                } else {
+                    return Some(Ok(builder.close()));
+                }
             }
-        } else {
-            return Some(Ok(builder.close()));
         }
     }
 }
 
-fn intersect_facets_at_plane(facets: &[BoundedFacet], plane: i64) -> Result<Vec<SliceIsland>, Error> {
+fn intersect_facets_at_plane<'a>(facets: impl IntoIterator<Item=&'a BoundedFacet>, plane: i64) -> Result<Vec<SliceIsland>, Error> {
     let mut segments = Vec::new();
     for facet in facets {
         let vs = facet.vertices();
@@ -151,55 +440,510 @@ fn intersect_facets_at_plane(facets: &[BoundedFacet], plane: i64) -> Result<Vec<
                         have_vertex_on_plane = true;
                     }
                 }
-                assert!(idx < 2, "{:?} intersected more than twice with plane z={}", facet, plane);
+                if idx == 2 {
+                    return Err(Error::FacetIntersection);
+                }
                 intersections[idx] = intersection;
                 idx += 1;
             }
         }
         // idx is 2 because it is still incremented after the last insertion into the array
-        assert_eq!(idx, 2, "{:?} didn't have two intersections with plane z={}", facet, plane);
+        if idx != 2 {
+            return Err(Error::FacetIntersection);
+        }
         segments.push(intersections);
     }
 
-    let mut islands = Vec::new();
-    while let Some(outline) = stitch_next(&mut segments) {
-        islands.push(SliceIsland {
-            outline: outline?,
-            // TODO: holes. stitch_next() currently treats holes like filled areas
-            holes: Vec::new(),
-        });
+    let mut polygons = Vec::new();
+    let mut stitcher = SegmentStitcher::new(segments, plane);
+    while let Some(polygon) = stitcher.next_polygon() {
+        polygons.push(polygon?);
     }
 
+    Ok(nest_polygons(polygons))
+}
+
+/// Intersects `facets` with the plane like [intersect_facets_at_plane], but separately for the facets of each tool,
+/// so that each island comes from the meshes of a single tool
+fn intersect_facets_by_tool<'a>(facets: impl IntoIterator<Item=&'a BoundedFacet>, plane: i64) -> Result<Vec<SliceIsland>, Error> {
+    let mut tools: BTreeMap<u8, Vec<&BoundedFacet>> = BTreeMap::new();
+    for facet in facets {
+        tools.entry(facet.tool()).or_default().push(facet);
+    }
+    let mut islands = Vec::new();
+    for (tool, facets) in tools {
+        for mut island in intersect_facets_at_plane(facets, plane)? {
+            island.tool = tool;
+            islands.push(island);
+        }
+    }
     Ok(islands)
 }
 
+/// Sorts the closed polygons of a slice into islands. A polygon inside an odd number of other polygons
+/// is a hole in the smallest polygon containing it, the rest are island outlines.
+///
+/// Outlines are made counterclockwise and holes clockwise.
+fn nest_polygons(polygons: Vec<Polygon>) -> Vec<SliceIsland> {
+    geometry::boolean::nest(polygons).into_iter().map(SliceIsland::from).collect()
+}
+
+/// A summary of slicing a scene, returned by [Slicer::validate]
+#[derive(Debug, Clone)]
+pub struct SliceStats {
+    /// Number of slices, including any raft layers
+    pub layer_count: usize,
+    /// Total number of islands in all of the slices
+    pub island_count: usize,
+    /// See [Slicer::slice_with_warnings]
+    pub warnings: Vec<Warning>,
+}
+
 /// Turns meshes into [Slice]s
 pub struct Slicer<'a> {
     config: &'a ConfigProfile,
+    cancel: Option<&'a AtomicBool>,
 }
 
 impl<'a> Slicer<'a> {
     pub fn new(config: &'a ConfigProfile) -> Self {
-        Self { config }
+        Self { config, cancel: None }
     }
 
-    /// Slices the given scene
+    /// Makes [Slicer::slice] stop and return [Error::Cancelled] as soon as `cancel` is set to `true`
+    /// (e.g. from another thread). It is checked before each layer.
+    pub fn with_cancel_flag(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Slices the given scene.
+    ///
+    /// Returns [Error::InvalidConfig] if the profile isn't valid (see [ConfigProfile::validate]), and
+    /// [Error::CoordinateOverflow] if the layers go higher than an `i64` can hold.
     pub fn slice(&self, scene: Scene) -> Result<Vec<Slice>, Error> {
+        Ok(self.slice_with_warnings(scene)?.0)
+    }
+
+    /// Like [Slicer::slice], but leaves the scene as it is, so that it can be sliced again (e.g. with another
+    /// profile). The facets are copied for slicing.
+    pub fn slice_ref(&self, scene: &Scene) -> Result<Vec<Slice>, Error> {
+        Ok(self.slice_ref_with_warnings(scene)?.0)
+    }
+
+    /// Like [Slicer::slice_with_warnings], but leaves the scene as it is (see [Slicer::slice_ref])
+    pub fn slice_ref_with_warnings(&self, scene: &Scene) -> Result<(Vec<Slice>, Vec<Warning>), Error> {
+        let warnings = self.check_scene(scene)?;
+        self.slice_facets(scene.facet_filter()?, warnings)
+    }
+
+    /// Slices the scene just to check that it can be sliced, e.g. to check a library of models without generating
+    /// G-code for them. Returns the same errors as [Slicer::slice].
+    pub fn validate(&self, scene: &Scene) -> Result<SliceStats, Error> {
+        let (slices, warnings) = self.slice_ref_with_warnings(scene)?;
+        Ok(SliceStats {
+            layer_count: slices.len(),
+            island_count: slices.iter().map(|slice| slice.islands.len()).sum(),
+            warnings,
+        })
+    }
+
+    /// Like [Slicer::slice], but also returns warnings about things that are probably mistakes
+    pub fn slice_with_warnings(&self, scene: Scene) -> Result<(Vec<Slice>, Vec<Warning>), Error> {
+        let warnings = self.check_scene(&scene)?;
+        self.slice_facets(scene.to_facet_filter()?, warnings)
+    }
+
+    /// Checks the profile and the scene before slicing, returning any warnings about them
+    fn check_scene(&self, scene: &Scene) -> Result<Vec<Warning>, Error> {
+        // the fields of a profile can be changed after it is built, and a zero layer height would never finish
+        self.config.validate()?;
         if scene.is_empty() { return Err(Error::EmptyScene); }
-        let mut ff = scene.to_facet_filter();
+        if let Some(volume) = &self.config.build_volume {
+            scene.fit_check(volume.clone())?;
+        }
+        let mut warnings = Vec::new();
+        if let Some((lowest, highest)) = scene.z_bounds() {
+            let model_height = (highest - lowest) as u64;
+            if self.config.layer_height > model_height / 2 {
+                warnings.push(Warning::LayerHeightTooLarge { layer_height: self.config.layer_height, model_height });
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Slices the facets of a scene that has been checked by [Slicer::check_scene]
+    fn slice_facets(&self, mut ff: FacetFilter, mut warnings: Vec<Warning>) -> Result<(Vec<Slice>, Vec<Warning>), Error> {
         let mut slices = Vec::new();
+        // bottom of the layer being sliced
+        let mut bottom = ff.current_height();
+        let model_bottom = bottom;
 
-        while !ff.is_empty() {
-            let plane = ff.current_height();
+        loop {
+            if self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+                return Err(Error::Cancelled);
+            }
+            let thickness = match (&self.config.first_layer_height, &self.config.adaptive_layer_height) {
+                (Some(first_layer_height), _) if slices.is_empty() => *first_layer_height,
+                (_, Some(adaptive)) if !slices.is_empty() => Self::adaptive_layer_height(adaptive, &ff, bottom),
+                _ => self.config.layer_height,
+            };
+            // slice through the middle of the layer, so that layers resting on a flat surface (like the
+            // bottom of the model) aren't sliced exactly along that surface
+            let plane = bottom.checked_add((thickness / 2) as i64).ok_or(Error::CoordinateOverflow)?;
+            ff.advance_height((plane - ff.current_height()) as u64);
+            if ff.is_empty() {
+                break;
+            }
             let facets = ff.intersecting_facets();
-            let islands = intersect_facets_at_plane(facets, plane)?;
+            let mut islands = intersect_facets_by_tool(facets, plane)?;
+            if self.config.vase_mode && (islands.len() > 1 || islands.iter().any(|island| !island.holes.is_empty())) {
+                return Err(Error::VaseModeMultipleContours(slices.len()));
+            }
+            for island in &mut islands {
+                if let Some(tolerance) = self.config.simplify_tolerance {
+                    island.simplify(tolerance as i64);
+                }
+                self.add_perimeters(island);
+            }
             slices.push(Slice {
                 islands,
-                thickness: self.config.layer_height,
+                thickness,
+                air_gap: 0,
+                skirt: Vec::new(),
+            });
+            bottom = i64::try_from(thickness).ok()
+                .and_then(|thickness| bottom.checked_add(thickness))
+                .ok_or(Error::CoordinateOverflow)?;
+        }
+
+        // infill depends on the layers above and below, so it can only be added once they're all sliced.
+        // Vases are hollow.
+        if !self.config.vase_mode {
+            let infill: Vec<Vec<_>> = (0..slices.len())
+                .map(|layer| slices[layer].islands.iter().map(|island| self.infill(island, &slices, layer)).collect())
+                .collect();
+            for (slice, infill) in slices.iter_mut().zip(infill) {
+                for (island, infill) in slice.islands.iter_mut().zip(infill) {
+                    island.infill = infill.sparse;
+                    island.solid_infill = infill.solid;
+                    island.bridge_infill = infill.bridge;
+                    island.ironing = infill.ironing;
+                }
+            }
+        }
+        // find runs of empty layers. Slicing stops at the top of the model, so there is always something above them
+        let mut height = model_bottom;
+        let mut gap_bottom = None;
+        for slice in &slices {
+            if slice.is_empty() {
+                gap_bottom.get_or_insert(height);
+            } else if let Some(gap_bottom) = gap_bottom.take() {
+                warnings.push(Warning::EmptyLayers { bottom: gap_bottom, top: height });
+            }
+            height += (slice.air_gap + slice.thickness) as i64;
+        }
+        if self.config.skip_empty_layers {
+            // the first slice above each gap starts that much higher instead
+            let mut gap = 0;
+            slices.retain_mut(|slice| {
+                if slice.is_empty() {
+                    gap += slice.air_gap + slice.thickness;
+                    false
+                } else {
+                    slice.air_gap += std::mem::take(&mut gap);
+                    true
+                }
             });
-            ff.advance_height(self.config.layer_height);
         }
 
-        Ok(slices)
+        if self.config.raft_layers > 0 {
+            let raft = self.raft(&slices);
+            if let Some(first_slice) = slices.first_mut() {
+                first_slice.air_gap += self.config.raft_air_gap;
+            }
+            slices.splice(0..0, raft);
+        }
+        if let Some(first_slice) = slices.first_mut() {
+            if self.config.raft_layers == 0 {
+                for island in &mut first_slice.islands {
+                    island.brim = self.brim(island);
+                }
+            }
+            first_slice.skirt = self.skirt(first_slice);
+        }
+
+        Ok((slices, warnings))
+    }
+
+    /// The thickness for a layer starting at `bottom`, based on how close to horizontal the facets that
+    /// the thickest possible layer would pass through are. Horizontal facets are ignored, because a layer
+    /// boundary can line up with them exactly.
+    fn adaptive_layer_height(adaptive: &AdaptiveLayerHeight, ff: &FacetFilter, bottom: i64) -> u64 {
+        let (min, max) = (adaptive.min_layer_height, adaptive.max_layer_height);
+        let flatness = ff.facets_starting_below(bottom + max as i64).iter()
+            .filter(|facet| facet.upper_z_bound() > bottom && facet.lower_z_bound() != facet.upper_z_bound())
+            .map(BoundedFacet::flatness)
+            .fold(0.0, f64::max);
+        max - ((max - min) as f64 * flatness).round() as u64
+    }
+
+    /// Slices the scene with a single plane at height `z`, without slicing any of the layers below it.
+    ///
+    /// The returned islands are the raw cross-section: they have outlines and holes, but no perimeters
+    /// or infill.
+    pub fn slice_at(&self, scene: &Scene, z: i64) -> Result<Vec<SliceIsland>, Error> {
+        if scene.is_empty() { return Err(Error::EmptyScene); }
+        intersect_facets_by_tool(&scene.facets_intersecting(z), z)
+    }
+
+    /// Like [Slicer::slice_at], but finds the facets to slice using `index`. This is faster when slicing the same
+    /// scene at many heights, e.g. for a preview.
+    pub fn slice_at_index(&self, index: &FacetIndex, z: i64) -> Result<Vec<SliceIsland>, Error> {
+        intersect_facets_by_tool(index.intersecting_facets(z), z)
+    }
+
+    /// Generates `config.skirt_loops` loops around the islands of `slice`. There is one skirt around all
+    /// of the islands together, following their convex hull, rather than a separate one for each island.
+    fn skirt(&self, slice: &Slice) -> Vec<Polygon> {
+        let outline_vertices: Vec<Vector2D> = slice.islands.iter()
+            .flat_map(|island| island.outline.vertices())
+            .cloned()
+            .collect();
+        let hull = match geometry::convex_hull(&outline_vertices) {
+            Some(hull) => hull,
+            None => return Vec::new(),
+        };
+        let width = self.config.extrusion_width() as i64;
+        // the gap is between the skirt and the brim, if there is one
+        let distance = self.config.skirt_distance as i64 + width * self.config.brim_loops as i64;
+        (0..self.config.skirt_loops as i64)
+            // the hull is counterclockwise, so negative offsets go outwards
+            .flat_map(|i| hull.offset(-(distance + width / 2 + width * i)))
+            .collect()
+    }
+
+    /// Generates the layers of the raft (see [ConfigProfile::raft_layers]) to go under `slices`. Each has a
+    /// single perimeter, and is filled in with lines two extrusion widths apart, except for the top layer
+    /// which is filled solid.
+    fn raft(&self, slices: &[Slice]) -> Vec<Slice> {
+        // the outlines are counterclockwise, so negative offsets go outwards. Holes in the islands are
+        // covered by the raft.
+        let footprint = slices.first().into_iter()
+            .flat_map(|slice| &slice.islands)
+            .flat_map(|island| island.outline.offset(-(self.config.raft_margin as i64)))
+            .fold(Vec::new(), |footprint, outline| boolean::union(&footprint, &[Region { outline, holes: Vec::new() }]));
+        if footprint.is_empty() {
+            return Vec::new();
+        }
+        let width = self.config.extrusion_width() as i64;
+        // loops (going either way around) `inset` inside the edges of `region`
+        let inset = |region: &Region, inset: i64| -> Vec<Polygon> {
+            let mut loops = region.outline.offset(inset);
+            for hole in &region.holes {
+                loops.extend(hole.offset(-inset));
+            }
+            loops
+        };
+
+        (0..self.config.raft_layers).map(|layer| {
+            let is_top = layer == self.config.raft_layers - 1;
+            let islands = footprint.iter().map(|region| {
+                let mut island = SliceIsland::from(region.clone());
+                island.perimeters = vec![inset(region, width / 2)];
+                let spacing = if is_top { width } else { width * 2 };
+                let lines = infill::rectilinear(&inset(region, width + width / 2), spacing, layer % 2 == 1, |_, spans| spans);
+                if is_top {
+                    island.solid_infill = lines;
+                } else {
+                    island.infill = lines;
+                }
+                island
+            }).collect();
+            Slice {
+                thickness: self.config.layer_height,
+                air_gap: 0,
+                islands,
+                skirt: Vec::new(),
+            }
+        }).collect()
+    }
+
+    /// Generates `config.brim_loops` loops around the outline of `island`, touching it and each other
+    fn brim(&self, island: &SliceIsland) -> Vec<Polygon> {
+        let width = self.config.extrusion_width() as i64;
+        (0..self.config.brim_loops as i64).rev()
+            // the outline is counterclockwise, so negative offsets go outwards
+            .flat_map(|i| island.outline.offset(-(width / 2 + width * i)))
+            .collect()
+    }
+
+    /// Generates `config.perimeters` inset loops (only one in vase mode) along the outline and holes of
+    /// `island`. Stops early if the island is too thin to fit any more loops.
+    fn add_perimeters(&self, island: &mut SliceIsland) {
+        let width = self.config.extrusion_width() as i64;
+        // a vase is printed as a single wall
+        let perimeters = if self.config.vase_mode { self.config.perimeters.min(1) } else { self.config.perimeters };
+        for i in 0..perimeters as i64 {
+            // loops are centered half an extrusion width inside the previous loop's edge
+            let inset = width / 2 + width * i;
+            let mut loops = island.outline.offset(inset);
+            if loops.is_empty() {
+                break;
+            }
+            for hole in &island.holes {
+                loops.extend(hole.offset(-inset));
+            }
+            island.perimeters.push(loops);
+        }
+    }
+
+    /// Generates the infill lines for `island`, which is in `slices[layer]`. Parts of the island that
+    /// have nothing under them in the slice below are bridges. Of the rest, parts that don't have
+    /// `config.top_layers` layers above them or `config.bottom_layers` below them are filled solid, and
+    /// the rest is filled with `config.infill_density`.
+    ///
+    /// The sparse and solid lines alternate between horizontal and vertical each layer. If
+    /// `config.ironing` is set, parts with nothing above them are also ironed, crossing the solid lines.
+    fn infill(&self, island: &SliceIsland, slices: &[Slice], layer: usize) -> Infill {
+        let width = self.config.extrusion_width() as i64;
+        // the infill lines end where they would touch the innermost perimeter
+        let inset = width * self.config.perimeters as i64 + width / 2;
+        let mut boundary = island.outline.offset(inset);
+        if boundary.is_empty() {
+            return Infill { sparse: Vec::new(), solid: Vec::new(), bridge: Vec::new(), ironing: Vec::new() };
+        }
+        for hole in &island.holes {
+            boundary.extend(hole.offset(-inset));
+        }
+        let vertical = layer % 2 == 1;
+
+        let (top_layers, bottom_layers) = (self.config.top_layers as usize, self.config.bottom_layers as usize);
+        // the parts of a scanline that are covered by all of the nearby layers above and below. Layers
+        // past the top or bottom of the model don't cover anything.
+        let covered = |position: i64, mut spans: Vec<(i64, i64)>| {
+            if layer < bottom_layers || layer + top_layers >= slices.len() {
+                return Vec::new();
+            }
+            for other in (layer - bottom_layers..=layer + top_layers).filter(|&other| other != layer) {
+                if spans.is_empty() {
+                    break;
+                }
+                spans = infill::intersect_spans(&spans, &slices[other].scanline_spans(position, vertical));
+            }
+            spans
+        };
+
+        // the parts of a scanline that are over the slice below. The first slice is supported by the bed.
+        let supported = |position: i64, vertical: bool, spans: Vec<(i64, i64)>| match layer.checked_sub(1) {
+            Some(below) => infill::intersect_spans(&spans, &slices[below].scanline_spans(position, vertical)),
+            None => spans,
+        };
+
+        let solid = infill::rectilinear(&boundary, width, vertical, |position, spans| {
+            let spans = supported(position, vertical, spans);
+            infill::subtract_spans(&spans, &covered(position, spans.clone()))
+        });
+        let density = self.config.infill_density.min(1.0);
+        let sparse = if density > 0.0 {
+            let spacing = (width as f64 / density as f64).round() as i64;
+            infill::rectilinear(&boundary, spacing, vertical, |position, spans| {
+                covered(position, supported(position, vertical, spans))
+            })
+        } else {
+            Vec::new()
+        };
+        let [horizontal_bridge, vertical_bridge] = [false, true].map(|vertical| {
+            infill::rectilinear(&boundary, width, vertical, |position, spans| {
+                infill::subtract_spans(&spans, &supported(position, vertical, spans.clone()))
+            })
+        });
+        // bridge lines need to be held up at both ends, so use whichever direction has more ends that continue
+        // onto the slice below. If that's a tie, shorter lines sag less.
+        let anchored_ends = |lines: &[[Vector2D; 2]]| lines.iter()
+            .flat_map(|[start, end]| {
+                let length = start.distance_to(end).max(1.0);
+                // one extrusion width past each end of the line
+                let beyond = |from: &Vector2D, to: &Vector2D| Vector2D::new(
+                    to.x + ((to.x - from.x) as f64 / length * width as f64).round() as i64,
+                    to.y + ((to.y - from.y) as f64 / length * width as f64).round() as i64,
+                );
+                [beyond(start, end), beyond(end, start)]
+            })
+            .filter(|point| layer > 0 && slices[layer - 1].contains(point))
+            .count();
+        let longest_line = |lines: &[[Vector2D; 2]]| lines.iter()
+            .map(|[start, end]| start.distance_to(end))
+            .fold(0.0, f64::max);
+        let vertical_is_better = match anchored_ends(&vertical_bridge).cmp(&anchored_ends(&horizontal_bridge)) {
+            std::cmp::Ordering::Equal => longest_line(&vertical_bridge) < longest_line(&horizontal_bridge),
+            ordering => ordering.is_gt(),
+        };
+        let bridge = if vertical_is_better { vertical_bridge } else { horizontal_bridge };
+        let ironing = if self.config.ironing {
+            infill::rectilinear(&boundary, self.config.ironing_spacing as i64, !vertical, |position, spans| {
+                match slices.get(layer + 1) {
+                    Some(above) => infill::subtract_spans(&spans, &above.scanline_spans(position, !vertical)),
+                    None => spans,
+                }
+            })
+        } else {
+            Vec::new()
+        };
+        Infill { sparse, solid, bridge, ironing }
+    }
+}
+
+/// The infill lines of an island, as generated by [Slicer::infill]
+struct Infill {
+    sparse: Vec<[Vector2D; 2]>,
+    solid: Vec<[Vector2D; 2]>,
+    bridge: Vec<[Vector2D; 2]>,
+    ironing: Vec<[Vector2D; 2]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Mesh;
+
+    /// A 10mm cube with its lowest corner at `x` (in nanometers) along the x axis
+    fn cube(x: i64) -> Mesh {
+        let size = 10_000_000;
+        // bit 0 of each index picks the x coordinate, bit 1 y, and bit 2 z
+        let vertices: Vec<Vector3D> = (0..8)
+            .map(|corner| Vector3D::new(x + size * (corner & 1), size * (corner >> 1 & 1), size * (corner >> 2 & 1)))
+            .collect();
+        Mesh::from_indexed(&vertices, &[
+            [0, 2, 3], [0, 3, 1], [4, 5, 7], [4, 7, 6],
+            [0, 1, 5], [0, 5, 4], [2, 6, 7], [2, 7, 3],
+            [0, 4, 6], [0, 6, 2], [1, 3, 7], [1, 7, 5],
+        ]).unwrap()
+    }
+
+    #[test]
+    fn raft_follows_the_islands() {
+        let config = ConfigProfile::builder().raft_layers(2).raft_margin(3_000_000).build().unwrap();
+        let raft_islands = |cube_positions: &[i64]| {
+            let mut scene = Scene::new();
+            for &x in cube_positions {
+                scene.add_mesh(cube(x));
+            }
+            let slices = Slicer::new(&config).slice(scene).unwrap();
+            slices[0].islands().iter().map(|island| island.outline().clone()).collect::<Vec<_>>()
+        };
+
+        // cubes far apart get a raft each, instead of one spanning the gap between them
+        let mut extents: Vec<(i64, i64)> = raft_islands(&[0, 30_000_000]).iter()
+            .map(|outline| {
+                let xs = outline.vertices().iter().map(|vertex| vertex.x);
+                (xs.clone().min().unwrap(), xs.max().unwrap())
+            })
+            .collect();
+        extents.sort();
+        assert_eq!(extents, [(-3_000_000, 13_000_000), (27_000_000, 43_000_000)]);
+
+        // cubes closer than twice the margin share one raft
+        assert_eq!(raft_islands(&[0, 14_000_000]).len(), 1);
     }
 }